@@ -1,44 +1,735 @@
 //! Command modeling for safe CLI construction.
 
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Default)]
+use crate::error::SyntaxError;
+use crate::tmpl::{FuncResolver, Template, VariableResolver};
+pub mod parser;
+mod template;
+mod wrap;
+
+pub use template::{ArgTemplate, CommandTemplate};
+pub use wrap::Wrapper;
+
+/// Shown in place of secret values.
+pub const REDACTED: &str = "***";
+
+/// A single command. Program, arguments, env values and paths are
+/// `OsString`s so non-UTF-8 names from the filesystem survive exactly.
+#[derive(Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct CommandSpec {
-    pub program: String,
-    pub args: Vec<String>,
-    pub env: BTreeMap<String, String>,
-    pub cwd: Option<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serial::os"))]
+    pub program: OsString,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serial::os_vec", skip_serializing_if = "Vec::is_empty"))]
+    pub args: Vec<OsString>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serial::os_map", skip_serializing_if = "BTreeMap::is_empty"))]
+    pub env: BTreeMap<String, OsString>,
+    /// Variables to unset from the inherited environment.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeSet::is_empty"))]
+    pub env_remove: BTreeSet<String>,
+    /// Start from an empty environment, keeping only `env_keep` (plus `env`).
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_false"))]
+    pub env_clear: bool,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeSet::is_empty"))]
+    pub env_keep: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serial::os_opt", skip_serializing_if = "Option::is_none"))]
+    pub cwd: Option<OsString>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default"))]
     pub stdin: Stdio,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default"))]
     pub stdout: Stdio,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default"))]
     pub stderr: Stdio,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default"))]
     pub flags: CmdFlags,
+    /// What counts as success. For a pipeline, the last stage's criteria
+    /// judge the pipeline's status and output.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default"))]
+    pub success: SuccessCriteria,
+    /// Secret values. Wherever one appears in an argument, env value or cwd,
+    /// plans and `Debug` output show [`REDACTED`] instead; rendering for
+    /// execution and the executor use the real value.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serial::os_vec", skip_serializing_if = "Vec::is_empty"))]
+    pub secrets: Vec<OsString>,
+}
+
+impl fmt::Debug for CommandSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = self.redacted();
+        f.debug_struct("CommandSpec")
+            .field("program", &r.program)
+            .field("args", &r.args)
+            .field("env", &r.env)
+            .field("env_remove", &r.env_remove)
+            .field("env_clear", &r.env_clear)
+            .field("env_keep", &r.env_keep)
+            .field("cwd", &r.cwd)
+            .field("stdin", &r.stdin)
+            .field("stdout", &r.stdout)
+            .field("stderr", &r.stderr)
+            .field("flags", &r.flags)
+            .field("success", &r.success)
+            .field("secrets", &format_args!("[{} hidden]", self.secrets.len()))
+            .finish()
+    }
+}
+
+/// When a run counts as successful. The default accepts status 0 only.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct SuccessCriteria {
+    /// Accepted exit statuses; empty means just 0.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeSet::is_empty"))]
+    pub statuses: BTreeSet<i32>,
+    /// Stdout must contain this text.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub stdout_contains: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_false"))]
+    pub stdout_non_empty: bool,
+}
+
+impl SuccessCriteria {
+    pub fn accepts(&self, status: i32, stdout: &[u8]) -> bool {
+        let status_ok = if self.statuses.is_empty() { status == 0 } else { self.statuses.contains(&status) };
+        status_ok
+            && (!self.stdout_non_empty || !stdout.is_empty())
+            && self.stdout_contains.as_ref().is_none_or(|p| p.is_empty() || stdout.windows(p.len()).any(|w| w == p.as_bytes()))
+    }
+
+    /// Whether the criteria differ from "status 0".
+    pub fn is_custom(&self) -> bool {
+        !(self.statuses.is_empty() || self.statuses.len() == 1 && self.statuses.contains(&0))
+            || self.stdout_contains.is_some()
+            || self.stdout_non_empty
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+impl CommandSpec {
+    /// Parse a single POSIX shell command (see `PipelineSpec::parse`).
+    pub fn parse(input: &str) -> Result<Self, SyntaxError> { parser::posix::parse_cmd(input) }
+
+    /// Start building a command: `CommandSpec::new("git").arg("commit").build()?`.
+    pub fn new(program: impl Into<OsString>) -> Self {
+        CommandSpec { program: program.into(), ..Default::default() }
+    }
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self { self.args.push(arg.into()); self }
+    pub fn args<I, S>(mut self, args: I) -> Self where I: IntoIterator<Item = S>, S: Into<OsString> {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<OsString>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+    pub fn env_remove(mut self, key: impl Into<String>) -> Self { self.env_remove.insert(key.into()); self }
+    /// Run with an empty environment; combine with `env_keep`/`env`.
+    pub fn env_clear(mut self) -> Self { self.env_clear = true; self }
+    /// Keep an inherited variable when the environment is cleared.
+    pub fn env_keep(mut self, key: impl Into<String>) -> Self { self.env_keep.insert(key.into()); self }
+    /// Add an argument whose value is hidden in plans and `Debug` output.
+    pub fn secret_arg(self, arg: impl Into<OsString>) -> Self {
+        let arg = arg.into();
+        self.secret(arg.clone()).arg(arg)
+    }
+    /// Set an env variable whose value is hidden in plans and `Debug` output.
+    pub fn secret_env(self, key: impl Into<String>, value: impl Into<OsString>) -> Self {
+        let value = value.into();
+        self.secret(value.clone()).env(key, value)
+    }
+    /// Mark a value as secret wherever it appears in the command.
+    pub fn secret(mut self, value: impl Into<OsString>) -> Self {
+        let value = value.into();
+        if !value.is_empty() && !self.secrets.contains(&value) { self.secrets.push(value); }
+        self
+    }
+    pub fn cwd(mut self, dir: impl Into<OsString>) -> Self { self.cwd = Some(dir.into()); self }
+    pub fn stdin(mut self, io: Stdio) -> Self { self.stdin = io; self }
+    pub fn stdout(mut self, io: Stdio) -> Self { self.stdout = io; self }
+    pub fn stderr(mut self, io: Stdio) -> Self { self.stderr = io; self }
+    pub fn stdin_from(self, path: impl Into<OsString>) -> Self { self.stdin(Stdio::File { path: path.into(), append: false }) }
+    pub fn stdin_text(self, text: impl Into<String>) -> Self { self.stdin(Stdio::Text(text.into())) }
+    pub fn stdout_to(self, path: impl Into<OsString>) -> Self { self.stdout(Stdio::File { path: path.into(), append: false }) }
+    pub fn stdout_append(self, path: impl Into<OsString>) -> Self { self.stdout(Stdio::File { path: path.into(), append: true }) }
+    pub fn stderr_to(self, path: impl Into<OsString>) -> Self { self.stderr(Stdio::File { path: path.into(), append: false }) }
+    pub fn stderr_append(self, path: impl Into<OsString>) -> Self { self.stderr(Stdio::File { path: path.into(), append: true }) }
+    /// `2>&1`
+    pub fn stderr_to_stdout(self) -> Self { self.stderr(Stdio::Dup(1)) }
+    pub fn timeout(mut self, timeout: Duration) -> Self { self.flags.timeout_ms = Some(timeout.as_millis() as u64); self }
+    /// Retry a failing command up to `retries` more times, without waiting.
+    pub fn retries(mut self, retries: u32) -> Self { self.flags.retry = RetryPolicy::attempts(retries.saturating_add(1)); self }
+    pub fn retry(mut self, policy: RetryPolicy) -> Self { self.flags.retry = policy; self }
+    /// Accept these exit statuses as success (e.g. `[0, 1]` for `diff`).
+    pub fn success_statuses(mut self, statuses: impl IntoIterator<Item = i32>) -> Self {
+        self.success.statuses = statuses.into_iter().collect();
+        self
+    }
+    pub fn stdout_contains(mut self, text: impl Into<String>) -> Self { self.success.stdout_contains = Some(text.into()); self }
+    pub fn stdout_non_empty(mut self) -> Self { self.success.stdout_non_empty = true; self }
+    pub fn background(mut self) -> Self { self.flags.background = true; self }
+    pub fn nice(mut self, inc: i8) -> Self { self.flags.nice = Some(inc); self }
+    pub fn umask(mut self, mask: u32) -> Self { self.flags.umask = Some(mask); self }
+    pub fn rlimit(mut self, limit: Rlimit, value: u64) -> Self { self.flags.rlimits.insert(limit, value); self }
+    /// Pipe this command into `next`.
+    pub fn pipe(self, next: CommandSpec) -> PipelineSpec { PipelineSpec::from(self).pipe(next) }
+
+    /// Check the spec can be rendered and executed faithfully: non-empty
+    /// program, valid env keys, no NUL bytes, sensible stdio.
+    pub fn validate(&self) -> Result<(), SyntaxError> {
+        let invalid = |msg: String| Err(SyntaxError::InvalidArgument(msg));
+        if self.program.is_empty() { return invalid("program is empty".into()); }
+        if has_nul(&self.program) { return invalid("NUL byte in program".into()); }
+        for (i, a) in self.args.iter().enumerate() {
+            if has_nul(a) { return invalid(format!("NUL byte in argument {}", i)); }
+        }
+        for (k, v) in &self.env {
+            if !is_env_key(k) { return invalid(format!("invalid env key {:?}", k)); }
+            if has_nul(v) { return invalid(format!("NUL byte in env value for {}", k)); }
+        }
+        if let Some(k) = self.env_remove.iter().chain(&self.env_keep).find(|k| !is_env_key(k)) {
+            return invalid(format!("invalid env key {:?}", k));
+        }
+        if self.cwd.as_ref().is_some_and(|d| has_nul(d)) { return invalid("NUL byte in cwd".into()); }
+        for (fd, io) in [(0, &self.stdin), (1, &self.stdout), (2, &self.stderr)] {
+            match io {
+                Stdio::File { path, .. } if has_nul(path) => return invalid(format!("NUL byte in path for fd {}", fd)),
+                Stdio::Dup(to) if !matches!((fd, to), (1, 2) | (2, 1)) => return invalid(format!("unsupported fd duplication {}>&{}", fd, to)),
+                Stdio::Bytes(_) | Stdio::Text(_) if fd != 0 => return invalid(format!("in-memory data is only valid for stdin, not fd {}", fd)),
+                _ => {}
+            }
+        }
+        if matches!((&self.stdout, &self.stderr), (Stdio::Dup(2), Stdio::Dup(1))) {
+            return invalid("stdout and stderr cannot be duplicated onto each other".into());
+        }
+        if let Some(n) = self.flags.nice.filter(|n| !(-20..=19).contains(n)) { return invalid(format!("nice {} out of range -20..=19", n)); }
+        if let Some(m) = self.flags.umask.filter(|m| *m > 0o777) { return invalid(format!("umask {:o} out of range", m)); }
+        Ok(())
+    }
+
+    /// Finish a builder chain, validating the result.
+    pub fn build(self) -> Result<Self, SyntaxError> { self.validate()?; Ok(self) }
+
+    /// A copy for display with every secret replaced by [`REDACTED`].
+    pub fn redacted(&self) -> CommandSpec {
+        if self.secrets.is_empty() { return self.clone(); }
+        let mut c = self.clone();
+        c.program = self.redact(&c.program);
+        for a in &mut c.args { *a = self.redact(a); }
+        for v in c.env.values_mut() { *v = self.redact(v); }
+        if let Some(d) = &mut c.cwd { *d = self.redact(d); }
+        for io in [&mut c.stdin, &mut c.stdout, &mut c.stderr] {
+            match io {
+                Stdio::File { path, .. } => *path = self.redact(path),
+                Stdio::Text(t) => *t = self.redact(OsStr::new(t.as_str())).to_string_lossy().into_owned(),
+                Stdio::Bytes(b) => *b = self.redact_bytes(b),
+                _ => {}
+            }
+        }
+        c
+    }
+
+    /// Replace secrets in `value`: an exact match always, occurrences inside
+    /// a longer value when it is valid Unicode.
+    pub fn redact(&self, value: &OsStr) -> OsString {
+        if self.secrets.iter().any(|s| s == value) { return REDACTED.into(); }
+        match value.to_str() {
+            Some(text) => self.secrets.iter()
+                .filter_map(|s| s.to_str())
+                .fold(text.to_string(), |t, s| t.replace(s, REDACTED))
+                .into(),
+            None => value.to_os_string(),
+        }
+    }
+
+    /// Replace every occurrence of a secret in raw bytes.
+    fn redact_bytes(&self, data: &[u8]) -> Vec<u8> {
+        self.secrets.iter().fold(data.to_vec(), |data, s| {
+            let s = s.as_encoded_bytes();
+            let mut out = Vec::with_capacity(data.len());
+            let mut i = 0;
+            while i < data.len() {
+                if data[i..].starts_with(s) {
+                    out.extend_from_slice(REDACTED.as_bytes());
+                    i += s.len();
+                } else {
+                    out.push(data[i]);
+                    i += 1;
+                }
+            }
+            out
+        })
+    }
+}
+
+fn has_nul(s: &OsStr) -> bool { s.as_encoded_bytes().contains(&0) }
+
+fn is_env_key(k: &str) -> bool {
+    let mut cs = k.chars();
+    matches!(cs.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && cs.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct CmdFlags {
     pub background: bool,
     pub timeout_ms: Option<u64>,
-    pub retries: u8,
+    /// When to re-run the command if it fails. For a pipeline, the last
+    /// stage's policy applies to the pipeline as a whole.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default", alias = "retries", deserialize_with = "crate::serial::retry"))]
+    pub retry: RetryPolicy,
+    /// Niceness increment (`nice -n`), -20..=19.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub nice: Option<i8>,
+    /// File mode creation mask, e.g. `0o027`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub umask: Option<u32>,
+    /// Resource limits, set as both soft and hard limit.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+    pub rlimits: BTreeMap<Rlimit, u64>,
+}
+
+impl CmdFlags {
+    /// Whether any process attribute (nice, umask, rlimits) is set.
+    pub fn has_process_attrs(&self) -> bool {
+        self.nice.is_some() || self.umask.is_some() || !self.rlimits.is_empty()
+    }
+}
+
+/// How often and when to re-run a failed command. `StdExecutor` enforces it;
+/// `PosixRenderer` can render it as a shell loop (without jitter).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct RetryPolicy {
+    /// Total runs including the first; 0 and 1 both mean no retries.
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+    /// Add a random delay of up to this many milliseconds to each wait.
+    pub jitter_ms: u64,
+    /// Exit statuses worth retrying.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeSet::is_empty"))]
+    pub on_status: BTreeSet<i32>,
+    /// Stderr substrings that mark a failure as worth retrying.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub on_stderr: Vec<String>,
+}
+
+/// Wait between attempts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "kind", content = "value", rename_all = "snake_case"))]
+pub enum Backoff {
+    Fixed { delay_ms: u64 },
+    /// `initial_ms * factor^(n-1)` before retry `n`, capped at `max_ms`.
+    Exponential { initial_ms: u64, factor: u32, max_ms: u64 },
+}
+
+impl Default for Backoff {
+    fn default() -> Self { Backoff::Fixed { delay_ms: 0 } }
 }
 
-#[derive(Debug, Clone)]
+impl RetryPolicy {
+    /// Run up to `max_attempts` times in total, without waiting.
+    pub fn attempts(max_attempts: u32) -> Self { RetryPolicy { max_attempts, ..Default::default() } }
+    pub fn backoff(mut self, backoff: Backoff) -> Self { self.backoff = backoff; self }
+    pub fn jitter(mut self, max: Duration) -> Self { self.jitter_ms = max.as_millis() as u64; self }
+    /// Only retry these exit statuses (or matching stderr, see [`Self::on_stderr`]).
+    pub fn on_status(mut self, status: i32) -> Self { self.on_status.insert(status); self }
+    /// Only retry when stderr contains `pattern` (or the status matches).
+    pub fn on_stderr(mut self, pattern: impl Into<String>) -> Self { self.on_stderr.push(pattern.into()); self }
+
+    pub fn retries(&self) -> u32 { self.max_attempts.saturating_sub(1) }
+
+    /// Whether a failed run that ended with `status` and `stderr` should be
+    /// retried, attempts permitting. Without conditions every failure is
+    /// retryable.
+    pub fn should_retry(&self, status: i32, stderr: &[u8]) -> bool {
+        if self.on_status.is_empty() && self.on_stderr.is_empty() { return true; }
+        self.on_status.contains(&status)
+            || self.on_stderr.iter().any(|p| p.is_empty() || stderr.windows(p.len()).any(|w| w == p.as_bytes()))
+    }
+
+    /// Wait before retry `n` (1-based), without jitter.
+    pub fn delay(&self, n: u32) -> Duration {
+        let ms = match self.backoff {
+            Backoff::Fixed { delay_ms } => delay_ms,
+            Backoff::Exponential { initial_ms, factor, max_ms } => {
+                initial_ms.saturating_mul(u64::from(factor).saturating_pow(n.saturating_sub(1))).min(max_ms)
+            }
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+/// A resource limit. Sizes are in bytes; shells get them in KiB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Rlimit {
+    /// CPU time in seconds (`ulimit -t`, `RLIMIT_CPU`).
+    Cpu,
+    /// Address space in bytes (`ulimit -v`, `RLIMIT_AS`).
+    Memory,
+    /// Data segment in bytes (`ulimit -d`, `RLIMIT_DATA`).
+    Data,
+    /// Stack in bytes (`ulimit -s`, `RLIMIT_STACK`).
+    Stack,
+    /// Open file descriptors (`ulimit -n`, `RLIMIT_NOFILE`).
+    OpenFiles,
+}
+
+impl Rlimit {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rlimit::Cpu => "cpu",
+            Rlimit::Memory => "memory",
+            Rlimit::Data => "data",
+            Rlimit::Stack => "stack",
+            Rlimit::OpenFiles => "open_files",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "kind", content = "value", rename_all = "snake_case"))]
 pub enum Stdio {
     Inherit,
     Null,
-    File { path: String, append: bool },
+    File {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serial::os"))]
+        path: OsString,
+        append: bool,
+    },
     Pipe,
+    /// Duplicate another standard fd: `Dup(1)` on stderr is `2>&1`,
+    /// `Dup(2)` on stdout is `>&2`. `&> file` is stdout `File` + stderr `Dup(1)`.
+    Dup(u8),
+    /// In-memory stdin data (here-doc on POSIX, echoed on cmd.exe).
+    Bytes(Vec<u8>),
+    Text(String),
 }
 
 impl Default for Stdio {
     fn default() -> Self { Stdio::Inherit }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct PipelineSpec(pub Vec<CommandSpec>);
+impl Stdio {
+    /// Render a template with the given resolvers into in-memory stdin.
+    pub fn template<V: VariableResolver, F: FuncResolver>(tpl: &Template, vars: &V, funcs: &F) -> Result<Self, SyntaxError> {
+        Ok(Stdio::Text(tpl.render(vars, funcs)?))
+    }
+
+    /// The data to feed, if this is an in-memory source.
+    pub fn in_memory(&self) -> Option<&[u8]> {
+        match self {
+            Stdio::Bytes(b) => Some(b),
+            Stdio::Text(s) => Some(s.as_bytes()),
+            _ => None,
+        }
+    }
+}
+
+/// Options that apply to a whole pipeline rather than a single stage.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PipeOpts {
+    /// Report the status of the rightmost failing stage instead of the last
+    /// stage's (`set -o pipefail`).
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_false"))]
+    pub pipefail: bool,
+    /// Send every stage's stderr down the pipe with its stdout (`|&`). The
+    /// last stage is unaffected; earlier stages must not redirect stderr
+    /// elsewhere themselves.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_false"))]
+    pub merge_stderr: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(from = "crate::serial::PipelineRepr"))]
+pub struct PipelineSpec {
+    pub stages: Vec<CommandSpec>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "crate::serial::is_default"))]
+    pub opts: PipeOpts,
+}
 
 impl PipelineSpec {
-    pub fn new() -> Self { PipelineSpec(Vec::new()) }
-    pub fn push(&mut self, cmd: CommandSpec) { self.0.push(cmd); }
-    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+    /// Parse a POSIX shell string with quoting, env prefixes, redirections
+    /// and `|` into a pipeline. Expansions, globs and lists are rejected.
+    pub fn parse(input: &str) -> Result<Self, SyntaxError> { parser::posix::parse_pipe(input) }
+    pub fn new() -> Self { PipelineSpec::default() }
+    pub fn push(&mut self, cmd: CommandSpec) { self.stages.push(cmd); }
+    pub fn is_empty(&self) -> bool { self.stages.is_empty() }
+
+    /// Append a stage: `PipelineSpec::new().pipe(a).pipe(b)`.
+    pub fn pipe(mut self, cmd: CommandSpec) -> Self { self.stages.push(cmd); self }
+    /// Run the pipeline in the background (flag on the last stage).
+    pub fn background(mut self) -> Self {
+        if let Some(last) = self.stages.last_mut() { last.flags.background = true; }
+        self
+    }
+    /// Fail if any stage fails (see [`PipeOpts::pipefail`]).
+    pub fn pipefail(mut self) -> Self { self.opts.pipefail = true; self }
+    /// Pipe stderr along with stdout between stages (see [`PipeOpts::merge_stderr`]).
+    pub fn merge_stderr(mut self) -> Self { self.opts.merge_stderr = true; self }
+
+    /// The stages as they run, with pipeline options folded in: under
+    /// `merge_stderr` every stage but the last gets `stderr: Dup(1)`. A
+    /// stage whose stderr already goes to a file or null is an error, since
+    /// the merge would discard that redirection.
+    pub fn effective_stages(&self) -> Result<impl Iterator<Item = Cow<'_, CommandSpec>> + '_, SyntaxError> {
+        self.check_merge()?;
+        let n = self.stages.len();
+        Ok(self.stages.iter().enumerate().map(move |(i, c)| {
+            if self.opts.merge_stderr && i + 1 < n {
+                Cow::Owned(CommandSpec { stderr: Stdio::Dup(1), ..c.clone() })
+            } else {
+                Cow::Borrowed(c)
+            }
+        }))
+    }
+
+    fn check_merge(&self) -> Result<(), SyntaxError> {
+        if !self.opts.merge_stderr { return Ok(()); }
+        let upstream = &self.stages[..self.stages.len().saturating_sub(1)];
+        match upstream.iter().find(|c| matches!(c.stderr, Stdio::File { .. } | Stdio::Null)) {
+            Some(c) => Err(SyntaxError::InvalidArgument(format!("merge_stderr conflicts with the stderr redirection of {:?}", c.program))),
+            None => Ok(()),
+        }
+    }
+
+    /// Finish a builder chain, validating every stage.
+    pub fn build(self) -> Result<Self, SyntaxError> {
+        if self.stages.is_empty() { return Err(SyntaxError::InvalidArgument("pipeline is empty".into())); }
+        for c in &self.stages { c.validate()?; }
+        self.check_merge()?;
+        Ok(self)
+    }
+}
+
+impl From<Vec<CommandSpec>> for PipelineSpec {
+    fn from(stages: Vec<CommandSpec>) -> Self { PipelineSpec { stages, ..Default::default() } }
+}
+
+impl From<CommandSpec> for PipelineSpec {
+    fn from(cmd: CommandSpec) -> Self { PipelineSpec { stages: vec![cmd], ..Default::default() } }
+}
+
+/// A command list built from pipelines: `&&`, `||`, `;`, subshells `( … )`
+/// and brace groups `{ …; }`. `&&` and `||` bind tighter than `;`, as in sh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "kind", content = "value", rename_all = "snake_case"))]
+pub enum ScriptSpec {
+    Pipe(PipelineSpec),
+    /// `a && b`
+    AndThen(Box<ScriptSpec>, Box<ScriptSpec>),
+    /// `a || b`
+    OrElse(Box<ScriptSpec>, Box<ScriptSpec>),
+    /// `a; b`
+    Always(Box<ScriptSpec>, Box<ScriptSpec>),
+    /// `( a )`
+    Subshell(Box<ScriptSpec>),
+    /// `{ a; }`
+    Group(Box<ScriptSpec>),
+}
+
+impl ScriptSpec {
+    pub fn and_then(self, next: impl Into<ScriptSpec>) -> Self { ScriptSpec::AndThen(Box::new(self), Box::new(next.into())) }
+    pub fn or_else(self, next: impl Into<ScriptSpec>) -> Self { ScriptSpec::OrElse(Box::new(self), Box::new(next.into())) }
+    pub fn then(self, next: impl Into<ScriptSpec>) -> Self { ScriptSpec::Always(Box::new(self), Box::new(next.into())) }
+    pub fn subshell(self) -> Self { ScriptSpec::Subshell(Box::new(self)) }
+    pub fn group(self) -> Self { ScriptSpec::Group(Box::new(self)) }
+
+    /// Rebuild the list with every pipeline passed through `f`.
+    pub fn try_map_pipes<F>(&self, f: &mut F) -> Result<ScriptSpec, SyntaxError>
+    where F: FnMut(&PipelineSpec) -> Result<PipelineSpec, SyntaxError> {
+        let mut both = |a: &ScriptSpec, b: &ScriptSpec| -> Result<(Box<ScriptSpec>, Box<ScriptSpec>), SyntaxError> {
+            Ok((Box::new(a.try_map_pipes(f)?), Box::new(b.try_map_pipes(f)?)))
+        };
+        Ok(match self {
+            ScriptSpec::Pipe(p) => ScriptSpec::Pipe(f(p)?),
+            ScriptSpec::AndThen(a, b) => { let (a, b) = both(a, b)?; ScriptSpec::AndThen(a, b) }
+            ScriptSpec::OrElse(a, b) => { let (a, b) = both(a, b)?; ScriptSpec::OrElse(a, b) }
+            ScriptSpec::Always(a, b) => { let (a, b) = both(a, b)?; ScriptSpec::Always(a, b) }
+            ScriptSpec::Subshell(inner) => ScriptSpec::Subshell(Box::new(inner.try_map_pipes(f)?)),
+            ScriptSpec::Group(inner) => ScriptSpec::Group(Box::new(inner.try_map_pipes(f)?)),
+        })
+    }
 }
 
+impl From<PipelineSpec> for ScriptSpec {
+    fn from(pipe: PipelineSpec) -> Self { ScriptSpec::Pipe(pipe) }
+}
+
+impl From<CommandSpec> for ScriptSpec {
+    fn from(cmd: CommandSpec) -> Self { ScriptSpec::Pipe(cmd.into()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{PosixRenderer, Renderer};
+
+    #[test]
+    fn parse_quoting_env_and_redirs() {
+        let p = PipelineSpec::parse(r#"cd '/w d' && LANG=C FOO="a \"b\" \$c" grep -e 'it'\''s' a\ b < in.txt 2>&1 | sort -u >> "out file" &"#).unwrap();
+        assert_eq!(p.stages.len(), 2);
+        let grep = &p.stages[0];
+        assert_eq!(grep.cwd.as_deref(), Some(OsStr::new("/w d")));
+        assert_eq!(grep.env.get("LANG").map(OsString::as_os_str), Some(OsStr::new("C")));
+        assert_eq!(grep.env.get("FOO").map(OsString::as_os_str), Some(OsStr::new("a \"b\" $c")));
+        assert_eq!(grep.program, "grep");
+        assert_eq!(grep.args, vec!["-e", "it's", "a b"]);
+        assert_eq!(grep.stdin, Stdio::File { path: "in.txt".into(), append: false });
+        assert_eq!(grep.stderr, Stdio::Dup(1));
+        assert_eq!(p.stages[1].stdout, Stdio::File { path: "out file".into(), append: true });
+        assert!(p.stages[1].flags.background);
+
+        let c = CommandSpec::parse("cat <<'EOF' > /dev/null\nline $x\nEOF\n").unwrap();
+        assert_eq!(c.stdin, Stdio::Text("line $x\n".into()));
+        assert_eq!(c.stdout, Stdio::Null);
+
+        let p = PipelineSpec::parse("(set -o pipefail && make 2>/dev/null |& tee log | wc -l) &").unwrap();
+        assert!(p.opts.pipefail);
+        assert_eq!(p.stages[0].stderr, Stdio::Dup(1));
+        assert_eq!(p.stages[1].stderr, Stdio::Inherit);
+        assert!(p.stages[2].flags.background);
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_constructs() {
+        let cases = [
+            ("echo $(id)", "command substitution is not supported at byte 5"),
+            ("echo \"`id`\"", "command substitution is not supported at byte 6"),
+            ("echo $HOME", "parameter expansion is not supported at byte 5"),
+            ("ls *.rs", "unquoted glob character `*` at byte 3"),
+            ("cat ~/x", "tilde expansion is not supported at byte 4"),
+            ("a && b", "`&&` lists are not supported (use ScriptSpec) at byte 2"),
+            ("a; b", "`;` lists are not supported (use ScriptSpec) at byte 1"),
+            ("echo 'open", "unterminated single quote at byte 5"),
+            ("cat <<EOF\nx\nEOF", "here-doc delimiter must be quoted (unquoted bodies are expanded) at byte 6"),
+            ("a | | b", "empty pipeline stage at byte 4"),
+            ("(echo hi)", "subshells are not supported at byte 9"),
+        ];
+        for (input, msg) in cases {
+            match PipelineSpec::parse(input) {
+                Err(SyntaxError::InvalidArgument(m)) => assert_eq!(m, msg, "input: {:?}", input),
+                other => panic!("{:?} parsed as {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn builder_composes_and_validates() {
+        let p = CommandSpec::new("git")
+            .arg("commit")
+            .args(["-m", "msg with spaces"])
+            .env("GIT_AUTHOR_NAME", "ci")
+            .cwd("/repo")
+            .stdout_to("log.txt")
+            .stderr_to_stdout()
+            .timeout(Duration::from_secs(2))
+            .pipe(CommandSpec::new("tee").arg("copy.txt"))
+            .background()
+            .build()
+            .unwrap();
+        let git = &p.stages[0];
+        assert_eq!(git.args, vec!["commit", "-m", "msg with spaces"]);
+        assert_eq!(git.stderr, Stdio::Dup(1));
+        assert_eq!(git.flags.timeout_ms, Some(2000));
+        assert!(p.stages[1].flags.background);
+        let rendered = PosixRenderer::default().render_pipe(&p).unwrap();
+        assert_eq!(rendered, "(cd '/repo' && GIT_AUTHOR_NAME='ci' git 'commit' '-m' 'msg with spaces' > 'log.txt' 2>&1) | tee 'copy.txt'");
+
+        let bad = |c: CommandSpec| matches!(c.build(), Err(SyntaxError::InvalidArgument(_)));
+        assert!(bad(CommandSpec::new("")));
+        assert!(bad(CommandSpec::new("echo").arg("a\0b")));
+        assert!(bad(CommandSpec::new("echo").stdout(Stdio::Dup(2)).stderr_to_stdout()));
+        assert!(bad(CommandSpec::new("echo").env("1BAD", "x")));
+        assert!(bad(CommandSpec::new("echo").env("OK", "x\0")));
+        assert!(bad(CommandSpec::new("echo").stdout(Stdio::Text("x".into()))));
+        assert!(bad(CommandSpec::new("echo").nice(20)));
+        assert!(bad(CommandSpec::new("echo").umask(0o1000)));
+        assert!(PipelineSpec::new().build().is_err());
+    }
+
+    #[test]
+    fn retry_policy_conditions_and_backoff() {
+        let any = RetryPolicy::attempts(3);
+        assert!(any.should_retry(1, b""));
+        let picky = RetryPolicy::attempts(3).on_status(75).on_stderr("connection reset");
+        assert!(picky.should_retry(75, b""));
+        assert!(picky.should_retry(1, b"error: connection reset by peer"));
+        assert!(!picky.should_retry(1, b"error: not found"));
+
+        let exp = RetryPolicy::attempts(6).backoff(Backoff::Exponential { initial_ms: 100, factor: 3, max_ms: 1000 });
+        let delays: Vec<u128> = (1..=5).map(|n| exp.delay(n).as_millis()).collect();
+        assert_eq!(delays, vec![100, 300, 900, 1000, 1000]);
+        assert_eq!(CommandSpec::new("x").retries(2).flags.retry.retries(), 2);
+    }
+
+    #[test]
+    fn success_criteria_classify() {
+        let grep = CommandSpec::new("grep").success_statuses([0, 1]);
+        assert!(grep.success.accepts(1, b"") && !grep.success.accepts(2, b"x"));
+        let probe = CommandSpec::new("curl").stdout_contains("\"ok\"").stdout_non_empty();
+        assert!(probe.success.accepts(0, br#"{"status":"ok"}"#));
+        assert!(!probe.success.accepts(0, b"{}") && !probe.success.accepts(7, b"\"ok\""));
+        assert!(!CommandSpec::new("x").success_statuses([0]).success.is_custom());
+    }
+
+    /// Tiny deterministic generator so the round-trip runs over many specs.
+    struct Lcg(u64);
+    impl Lcg {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % n
+        }
+        fn word(&mut self) -> String {
+            const ALPHABET: &[&str] = &["a", "Z", "0", " ", "'", "\"", "\\", "$", "`", "*", "?", "[", "~", "#", "&", "|", ";", "<", ">", "(", ")", "{", "}", "=", "%", "\t", "\n", "é", "-", "/", "!"];
+            (0..self.next(6)).map(|_| ALPHABET[self.next(ALPHABET.len())]).collect()
+        }
+        /// A word that, on Unix, sometimes carries bytes that are not UTF-8.
+        fn os_word(&mut self) -> OsString {
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStringExt;
+                let mut b = self.word().into_bytes();
+                if self.next(4) == 0 { b.insert(self.next(b.len() + 1), [0xff, 0x80, 0xc3][self.next(3)]); }
+                OsString::from_vec(b)
+            }
+            #[cfg(not(unix))]
+            { self.word().into() }
+        }
+        fn stdio(&mut self, fd: u8) -> Stdio {
+            match self.next(5) {
+                0 => Stdio::Null,
+                1 => Stdio::File { path: self.os_word(), append: fd != 0 && self.next(2) == 0 },
+                2 if fd != 0 => Stdio::Dup(3 - fd),
+                _ => Stdio::Inherit,
+            }
+        }
+    }
+
+    #[test]
+    fn parse_inverts_posix_render() {
+        let mut g = Lcg(7);
+        for _ in 0..500 {
+            let mut p = PipelineSpec::new();
+            for _ in 0..1 + g.next(3) {
+                let mut c = CommandSpec { program: format!("p{}", g.word()).into(), ..Default::default() };
+                c.args = (0..g.next(4)).map(|_| g.os_word()).collect();
+                for _ in 0..g.next(3) { c.env.insert(format!("K{}", g.next(100)), g.os_word()); }
+                if g.next(3) == 0 { c.cwd = Some(g.os_word()); }
+                c.stdin = match g.next(6) { 0 => Stdio::Text(format!("{}\n", g.word())), _ => g.stdio(0) };
+                c.stdout = g.stdio(1);
+                c.stderr = g.stdio(2);
+                p.push(c);
+            }
+            p.opts.pipefail = p.stages.len() > 1 && g.next(2) == 0;
+            let rendered = PosixRenderer::default().render_pipe(&p).unwrap();
+            assert_eq!(PipelineSpec::parse(&rendered).unwrap(), p, "rendered: {}", rendered);
+        }
+    }
+}
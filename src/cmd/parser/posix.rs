@@ -0,0 +1,465 @@
+use std::ffi::OsString;
+
+use super::super::{is_env_key, CommandSpec, PipelineSpec, Stdio};
+use crate::error::SyntaxError;
+
+#[derive(Debug)]
+struct Word {
+    /// Raw bytes; `$'\xNN'` quoting can produce non-UTF-8 values.
+    text: Vec<u8>,
+    /// Bytes of `text` before the first quoted or escaped character.
+    plain: usize,
+    pos: usize,
+}
+
+impl Word {
+    fn bare(&self) -> bool { self.plain == self.text.len() }
+    fn is(&self, s: &str) -> bool { self.text == s.as_bytes() }
+    fn into_os(self) -> Result<OsString, SyntaxError> { os_from_bytes(self.text, self.pos) }
+}
+
+#[cfg(unix)]
+fn os_from_bytes(b: Vec<u8>, _pos: usize) -> Result<OsString, SyntaxError> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(OsString::from_vec(b))
+}
+
+#[cfg(not(unix))]
+fn os_from_bytes(b: Vec<u8>, pos: usize) -> Result<OsString, SyntaxError> {
+    String::from_utf8(b).map(OsString::from).map_err(|_| err("non-UTF-8 word is not supported on this platform", pos))
+}
+
+fn push_char(text: &mut Vec<u8>, c: char) {
+    text.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+#[derive(Debug)]
+enum Tok {
+    Word(Word),
+    Pipe(usize),
+    /// `|&`: the stage's stderr joins its stdout in the pipe.
+    PipeAll(usize),
+    AndIf(usize),
+    Amp(usize),
+    Newline(usize),
+    LParen(usize),
+    RParen(usize),
+    /// `[fd]<`, `[fd]>`, `[fd]>>`
+    Redir { fd: Option<u8>, op: &'static str, pos: usize },
+    /// `[fd]>&N`
+    Dup { fd: Option<u8>, to: u8, pos: usize },
+    /// Here-doc body, filled in once the end of its line is reached.
+    HereDoc { body: String, pos: usize },
+}
+
+fn err(msg: &str, pos: usize) -> SyntaxError {
+    SyntaxError::InvalidArgument(format!("{} at byte {}", msg, pos))
+}
+
+const KEYWORDS: &[&str] = &["if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in", "function", "!"];
+
+struct Lexer<'a> {
+    chars: Vec<(usize, char)>,
+    input: &'a str,
+    i: usize,
+    toks: Vec<Tok>,
+    /// Indices into `toks` of here-docs awaiting their body, with delimiters.
+    pending: Vec<(usize, String)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self, off: usize) -> Option<char> { self.chars.get(self.i + off).map(|c| c.1) }
+    fn pos(&self) -> usize { self.chars.get(self.i).map(|c| c.0).unwrap_or(self.input.len()) }
+
+    fn run(mut self) -> Result<Vec<Tok>, SyntaxError> {
+        while let Some(ch) = self.peek(0) {
+            let pos = self.pos();
+            match ch {
+                ' ' | '\t' => { self.i += 1; }
+                '\n' => {
+                    self.i += 1;
+                    self.read_heredocs()?;
+                    self.toks.push(Tok::Newline(pos));
+                }
+                '|' => {
+                    if self.peek(1) == Some('|') { return Err(err("`||` lists are not supported (use ScriptSpec)", pos)); }
+                    if self.peek(1) == Some('&') { self.i += 2; self.toks.push(Tok::PipeAll(pos)); }
+                    else { self.i += 1; self.toks.push(Tok::Pipe(pos)); }
+                }
+                '&' => {
+                    if self.peek(1) == Some('&') { self.i += 2; self.toks.push(Tok::AndIf(pos)); }
+                    else if self.peek(1) == Some('>') { return Err(err("`&>` is not POSIX; use `> file 2>&1`", pos)); }
+                    else { self.i += 1; self.toks.push(Tok::Amp(pos)); }
+                }
+                ';' => return Err(err("`;` lists are not supported (use ScriptSpec)", pos)),
+                '(' => { self.i += 1; self.toks.push(Tok::LParen(pos)); }
+                ')' => { self.i += 1; self.toks.push(Tok::RParen(pos)); }
+                '<' | '>' => self.redir(None, pos)?,
+                '#' => return Err(err("comments are not supported", pos)),
+                _ => {
+                    // An all-digit word directly followed by `<`/`>` is an fd number.
+                    let mut j = 0;
+                    while matches!(self.peek(j), Some('0'..='9')) { j += 1; }
+                    if j > 0 && matches!(self.peek(j), Some('<') | Some('>')) {
+                        let digits = &self.input[pos..self.chars[self.i + j].0];
+                        let fd = match digits { "0" => 0, "1" => 1, "2" => 2, _ => return Err(err("only fds 0, 1 and 2 can be redirected", pos)) };
+                        self.i += j;
+                        self.redir(Some(fd), pos)?;
+                    } else {
+                        let w = self.word()?;
+                        self.toks.push(Tok::Word(w));
+                    }
+                }
+            }
+        }
+        if !self.pending.is_empty() {
+            return Err(err("here-doc body missing", self.input.len()));
+        }
+        Ok(self.toks)
+    }
+
+    fn redir(&mut self, fd: Option<u8>, pos: usize) -> Result<(), SyntaxError> {
+        let c0 = self.peek(0).unwrap_or_default();
+        let c1 = self.peek(1);
+        match (c0, c1) {
+            ('<', Some('<')) => {
+                if self.peek(2) == Some('-') { return Err(err("`<<-` here-docs are not supported", pos)); }
+                if fd.is_some_and(|f| f != 0) { return Err(err("here-docs can only feed stdin", pos)); }
+                self.i += 2;
+                self.skip_blanks();
+                let delim = self.word()?;
+                if delim.bare() { return Err(err("here-doc delimiter must be quoted (unquoted bodies are expanded)", delim.pos)); }
+                let text = String::from_utf8(delim.text).map_err(|_| err("here-doc delimiter is not UTF-8", delim.pos))?;
+                self.pending.push((self.toks.len(), text));
+                self.toks.push(Tok::HereDoc { body: String::new(), pos });
+            }
+            ('<', Some('&')) => return Err(err("stdin fd duplication is not supported", pos)),
+            ('<', Some('>')) => return Err(err("`<>` is not supported", pos)),
+            ('>', Some('|')) => return Err(err("`>|` is not supported", pos)),
+            ('>', Some('&')) => {
+                self.i += 2;
+                let to = match self.peek(0) {
+                    Some('1') => 1,
+                    Some('2') => 2,
+                    _ => return Err(err("only `2>&1` and `>&2` duplications are supported", pos)),
+                };
+                self.i += 1;
+                if self.peek(0).is_some_and(|c| !is_break(c)) {
+                    return Err(err("only `2>&1` and `>&2` duplications are supported", pos));
+                }
+                self.toks.push(Tok::Dup { fd, to, pos });
+            }
+            ('>', Some('>')) => { self.i += 2; self.toks.push(Tok::Redir { fd, op: ">>", pos }); }
+            ('>', _) => { self.i += 1; self.toks.push(Tok::Redir { fd, op: ">", pos }); }
+            _ => { self.i += 1; self.toks.push(Tok::Redir { fd, op: "<", pos }); }
+        }
+        Ok(())
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(0), Some(' ') | Some('\t')) { self.i += 1; }
+    }
+
+    fn word(&mut self) -> Result<Word, SyntaxError> {
+        let pos = self.pos();
+        let mut text: Vec<u8> = Vec::new();
+        let mut plain: Option<usize> = None;
+        let start = self.i;
+        while let Some(ch) = self.peek(0) {
+            let at = self.pos();
+            match ch {
+                c if is_break(c) => break,
+                '\'' => {
+                    plain.get_or_insert(text.len());
+                    self.i += 1;
+                    loop {
+                        match self.peek(0) {
+                            None => return Err(err("unterminated single quote", at)),
+                            Some('\'') => { self.i += 1; break; }
+                            Some(c) => { push_char(&mut text, c); self.i += 1; }
+                        }
+                    }
+                }
+                '"' => {
+                    plain.get_or_insert(text.len());
+                    self.i += 1;
+                    loop {
+                        let p = self.pos();
+                        match self.peek(0) {
+                            None => return Err(err("unterminated double quote", at)),
+                            Some('"') => { self.i += 1; break; }
+                            Some('\\') => match self.peek(1) {
+                                Some('\n') => { self.i += 2; }
+                                Some(c @ ('$' | '`' | '"' | '\\')) => { push_char(&mut text, c); self.i += 2; }
+                                _ => { text.push(b'\\'); self.i += 1; }
+                            },
+                            Some('$') => {
+                                if self.peek(1).is_some_and(|c| c != '"' && !c.is_whitespace()) {
+                                    return Err(expansion_err(self.peek(1), p));
+                                }
+                                text.push(b'$'); self.i += 1;
+                            }
+                            Some('`') => return Err(err("command substitution is not supported", p)),
+                            Some(c) => { push_char(&mut text, c); self.i += 1; }
+                        }
+                    }
+                }
+                '\\' => {
+                    match self.peek(1) {
+                        None => return Err(err("trailing backslash", at)),
+                        Some('\n') => { self.i += 2; }
+                        Some(c) => { plain.get_or_insert(text.len()); push_char(&mut text, c); self.i += 2; }
+                    }
+                }
+                '$' => {
+                    let next = self.peek(1);
+                    if next == Some('\'') {
+                        plain.get_or_insert(text.len());
+                        self.i += 2;
+                        self.ansi_c(&mut text, at)?;
+                        continue;
+                    }
+                    if next.is_some_and(|c| c == '(' || !is_break(c)) { return Err(expansion_err(next, at)); }
+                    text.push(b'$'); self.i += 1;
+                }
+                '`' => return Err(err("command substitution is not supported", at)),
+                '*' | '?' | '[' => return Err(err(&format!("unquoted glob character `{}`", ch), at)),
+                '{' | '}' => return Err(err(&format!("unquoted brace `{}`", ch), at)),
+                '~' if self.i == start || (plain.is_none() && is_assignment_prefix(&text)) => {
+                    return Err(err("tilde expansion is not supported", at));
+                }
+                c => { push_char(&mut text, c); self.i += 1; }
+            }
+        }
+        let plain = plain.unwrap_or(text.len());
+        Ok(Word { text, plain, pos })
+    }
+
+    /// Body of an ANSI-C `$'…'` string (bash/ksh/zsh, POSIX 2024), as
+    /// emitted by renderers for bytes that are not valid UTF-8.
+    fn ansi_c(&mut self, text: &mut Vec<u8>, at: usize) -> Result<(), SyntaxError> {
+        loop {
+            match self.peek(0) {
+                None => return Err(err("unterminated `$'` quote", at)),
+                Some('\'') => { self.i += 1; return Ok(()); }
+                Some('\\') => {
+                    let p = self.pos();
+                    let Some(e) = self.peek(1) else { return Err(err("unterminated `$'` quote", at)) };
+                    self.i += 2;
+                    let simple = match e {
+                        'n' => Some(b'\n'), 't' => Some(b'\t'), 'r' => Some(b'\r'),
+                        'a' => Some(7), 'b' => Some(8), 'e' | 'E' => Some(27), 'f' => Some(12), 'v' => Some(11),
+                        '\\' => Some(b'\\'), '\'' => Some(b'\''), '"' => Some(b'"'), '?' => Some(b'?'),
+                        _ => None,
+                    };
+                    if let Some(b) = simple { text.push(b); continue; }
+                    let (radix, max) = match e {
+                        'x' => (16, 2),
+                        '0'..='7' => { self.i -= 1; (8, 3) }
+                        _ => return Err(err(&format!("unsupported escape `\\{}` in `$'` quote", e), p)),
+                    };
+                    let mut v: u32 = 0;
+                    let mut n = 0;
+                    while n < max {
+                        match self.peek(0).and_then(|c| c.to_digit(radix)) {
+                            Some(d) => { v = v * radix + d; n += 1; self.i += 1; }
+                            None => break,
+                        }
+                    }
+                    if n == 0 || v > 0xff { return Err(err("invalid byte escape in `$'` quote", p)); }
+                    if v == 0 { return Err(err("NUL byte in `$'` quote", p)); }
+                    text.push(v as u8);
+                }
+                Some(c) => { push_char(text, c); self.i += 1; }
+            }
+        }
+    }
+
+    /// Consume here-doc bodies for the line just ended.
+    fn read_heredocs(&mut self) -> Result<(), SyntaxError> {
+        for (idx, delim) in std::mem::take(&mut self.pending) {
+            let start = self.pos();
+            let mut body = String::new();
+            loop {
+                if self.i >= self.chars.len() { return Err(err(&format!("here-doc delimiter `{}` not found", delim), start)); }
+                let line_start = self.pos();
+                while self.peek(0).is_some_and(|c| c != '\n') { self.i += 1; }
+                let line = &self.input[line_start..self.pos()];
+                if self.peek(0) == Some('\n') { self.i += 1; }
+                if line == delim { break; }
+                body.push_str(line);
+                body.push('\n');
+            }
+            if let Tok::HereDoc { body: b, .. } = &mut self.toks[idx] { *b = body; }
+        }
+        Ok(())
+    }
+}
+
+fn is_break(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+fn is_assignment_prefix(s: &[u8]) -> bool {
+    s.strip_suffix(b"=").and_then(|k| std::str::from_utf8(k).ok()).is_some_and(is_env_key)
+}
+
+fn expansion_err(next: Option<char>, pos: usize) -> SyntaxError {
+    match next {
+        Some('(') => err("command substitution is not supported", pos),
+        _ => err("parameter expansion is not supported", pos),
+    }
+}
+
+/// Parse a POSIX shell string into a pipeline.
+///
+/// Supports single/double/backslash quoting, `NAME=value` env prefixes,
+/// `<`, `>`, `>>`, `2>`, `2>>`, `2>&1`, `>&2`, quoted-delimiter here-docs,
+/// `|`, `|&`, a trailing `&`, and the renderers' `cd DIR && …` cwd prefix
+/// and `(set -o pipefail && …)` wrapper.
+/// Expansions, globbing, lists and other shell syntax are rejected.
+pub fn parse_pipe(input: &str) -> Result<PipelineSpec, SyntaxError> {
+    let lexer = Lexer { chars: input.char_indices().collect(), input, i: 0, toks: Vec::new(), pending: Vec::new() };
+    let mut toks = lexer.run()?;
+    while matches!(toks.last(), Some(Tok::Newline(_))) { toks.pop(); }
+    if let Some(Tok::Newline(p)) = toks.iter().find(|t| matches!(t, Tok::Newline(_))) {
+        return Err(err("multiple commands are not supported", *p));
+    }
+    let mut background = false;
+    if let Some(Tok::Amp(_)) = toks.last() { toks.pop(); background = true; }
+
+    let mut pipe = PipelineSpec::new();
+    let end = input.len();
+    if is_pipefail_wrapper(&toks) {
+        toks.pop();
+        toks.drain(..5);
+        pipe.opts.pipefail = true;
+    }
+    let mut stage: Vec<Tok> = Vec::new();
+    let mut last_pos = 0;
+    for t in toks {
+        match t {
+            Tok::Pipe(p) | Tok::PipeAll(p) => {
+                if stage.is_empty() { return Err(err("empty pipeline stage", p)); }
+                let mut cmd = parse_stage(std::mem::take(&mut stage), p)?;
+                if matches!(t, Tok::PipeAll(_)) { cmd.stderr = Stdio::Dup(1); }
+                pipe.push(cmd);
+                last_pos = p;
+            }
+            Tok::Amp(p) => return Err(err("background jobs are only supported at the end", p)),
+            t => stage.push(t),
+        }
+    }
+    if stage.is_empty() { return Err(err("empty pipeline stage", last_pos.max(end))); }
+    pipe.push(parse_stage(stage, end)?);
+    if let Some(last) = pipe.stages.last_mut() { last.flags.background = background; }
+    Ok(pipe)
+}
+
+/// `( set -o pipefail && … )` around a whole pipeline.
+fn is_pipefail_wrapper(toks: &[Tok]) -> bool {
+    let word = |i: usize, w: &str| matches!(toks.get(i), Some(Tok::Word(x)) if x.bare() && x.is(w));
+    toks.len() > 6
+        && matches!(toks[0], Tok::LParen(_))
+        && word(1, "set") && word(2, "-o") && word(3, "pipefail")
+        && matches!(toks[4], Tok::AndIf(_))
+        && matches!(toks.last(), Some(Tok::RParen(_)))
+}
+
+/// Parse a POSIX shell string holding a single command.
+pub fn parse_cmd(input: &str) -> Result<CommandSpec, SyntaxError> {
+    let mut pipe = parse_pipe(input)?;
+    if pipe.stages.len() != 1 { return Err(SyntaxError::InvalidArgument("expected a single command, found a pipeline".into())); }
+    Ok(pipe.stages.remove(0))
+}
+
+fn parse_stage(mut toks: Vec<Tok>, end: usize) -> Result<CommandSpec, SyntaxError> {
+    // Renderers isolate a stage's cwd as `(cd DIR && …)` inside pipelines.
+    let wrapped = matches!(toks.first(), Some(Tok::LParen(_)));
+    if wrapped {
+        match toks.pop() {
+            Some(Tok::RParen(_)) => { toks.remove(0); }
+            _ => return Err(err("unbalanced `(`", tok_pos(&toks[0]))),
+        }
+    }
+    if let Some(t) = toks.iter().find(|t| matches!(t, Tok::LParen(_) | Tok::RParen(_))) {
+        return Err(err("subshells are not supported", tok_pos(t)));
+    }
+
+    let mut cmd = CommandSpec::default();
+    let mut i = 0;
+    // `cd DIR && …` is how renderers express cwd
+    if let [Tok::Word(cd), Tok::Word(dir), Tok::AndIf(_), ..] = toks.as_slice() {
+        if cd.bare() && cd.is("cd") {
+            cmd.cwd = Some(os_from_bytes(dir.text.clone(), dir.pos)?);
+            i = 3;
+        }
+    }
+    if wrapped && cmd.cwd.is_none() {
+        return Err(err("subshells are not supported", end));
+    }
+
+    let mut have_program = false;
+    let mut toks = toks.into_iter().skip(i);
+    while let Some(t) = toks.next() {
+        match t {
+            Tok::Word(w) => {
+                if !have_program && w.plain > 0 {
+                    if let Some(eq) = w.text[..w.plain].iter().position(|&b| b == b'=') {
+                        if let Ok(key) = std::str::from_utf8(&w.text[..eq]) {
+                            if is_env_key(key) {
+                                let key = key.to_string();
+                                cmd.env.insert(key, os_from_bytes(w.text[eq + 1..].to_vec(), w.pos)?);
+                                continue;
+                            }
+                        }
+                    }
+                }
+                if have_program {
+                    cmd.args.push(w.into_os()?);
+                    continue;
+                }
+                if let Some(kw) = KEYWORDS.iter().find(|k| w.bare() && w.is(k)) {
+                    return Err(err(&format!("shell keyword `{}` is not supported", kw), w.pos));
+                }
+                cmd.program = w.into_os()?;
+                have_program = true;
+            }
+            Tok::AndIf(p) => return Err(err("`&&` lists are not supported (use ScriptSpec)", p)),
+            Tok::Redir { fd, op, pos } => {
+                let target = match toks.next() {
+                    Some(Tok::Word(w)) => w,
+                    _ => return Err(err("redirection without a target", pos)),
+                };
+                let io = if target.bare() && target.is("/dev/null") {
+                    Stdio::Null
+                } else {
+                    Stdio::File { path: target.into_os()?, append: op == ">>" }
+                };
+                match (fd, op) {
+                    (None | Some(0), "<") => cmd.stdin = io,
+                    (None | Some(1), ">" | ">>") => cmd.stdout = io,
+                    (Some(2), ">" | ">>") => cmd.stderr = io,
+                    _ => return Err(err("unsupported redirection", pos)),
+                }
+            }
+            Tok::Dup { fd, to, pos } => match (fd, to) {
+                (None | Some(1), 2) => cmd.stdout = Stdio::Dup(2),
+                (Some(2), 1) => cmd.stderr = Stdio::Dup(1),
+                _ => return Err(err("only `2>&1` and `>&2` duplications are supported", pos)),
+            },
+            Tok::HereDoc { body, .. } => cmd.stdin = Stdio::Text(body),
+            t => return Err(err("unexpected operator", tok_pos(&t))),
+        }
+    }
+    if !have_program { return Err(err("missing program", end)); }
+    Ok(cmd)
+}
+
+fn tok_pos(t: &Tok) -> usize {
+    match t {
+        Tok::Word(w) => w.pos,
+        Tok::Pipe(p) | Tok::PipeAll(p) | Tok::AndIf(p) | Tok::Amp(p) | Tok::Newline(p) | Tok::LParen(p) | Tok::RParen(p) => *p,
+        Tok::Redir { pos, .. } | Tok::Dup { pos, .. } | Tok::HereDoc { pos, .. } => *pos,
+    }
+}
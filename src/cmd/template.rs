@@ -0,0 +1,140 @@
+//! Commands whose argv elements are templates.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+use super::CommandSpec;
+use crate::error::SyntaxError;
+use crate::tmpl::{FuncResolver, Template, VariableResolver};
+
+/// One argv element (or env value, cwd, program): a literal, or a template
+/// rendered into exactly one element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "kind", content = "value", rename_all = "snake_case"))]
+pub enum ArgTemplate {
+    Lit(#[cfg_attr(feature = "serde", serde(with = "crate::serial::os"))] OsString),
+    Tpl(Template),
+}
+
+impl Default for ArgTemplate {
+    fn default() -> Self { ArgTemplate::Lit(OsString::new()) }
+}
+
+impl ArgTemplate {
+    /// Parse a bash-like `${VAR}` template (see `Template::parse`).
+    pub fn parse(input: &str) -> Result<Self, SyntaxError> { Ok(ArgTemplate::Tpl(Template::parse(input)?)) }
+
+    pub fn render<V: VariableResolver, F: FuncResolver>(&self, vars: &V, funcs: &F) -> Result<OsString, SyntaxError> {
+        match self {
+            ArgTemplate::Lit(s) => Ok(s.clone()),
+            ArgTemplate::Tpl(t) => Ok(t.render(vars, funcs)?.into()),
+        }
+    }
+}
+
+impl From<&str> for ArgTemplate {
+    fn from(s: &str) -> Self { ArgTemplate::Lit(s.into()) }
+}
+
+impl From<OsString> for ArgTemplate {
+    fn from(s: OsString) -> Self { ArgTemplate::Lit(s) }
+}
+
+impl From<Template> for ArgTemplate {
+    fn from(t: Template) -> Self { ArgTemplate::Tpl(t) }
+}
+
+/// A command with templated program, args, env values and cwd.
+///
+/// [`instantiate`](Self::instantiate) renders every element on its own and
+/// never re-splits the result, so a variable's value always lands in exactly
+/// one argv element: spaces, quotes, `;` or `$(…)` in it are plain bytes, and
+/// an empty value is an empty argument. Stdio, flags and the rest come from
+/// `base`; templated env entries and cwd override the base's.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct CommandTemplate {
+    pub program: ArgTemplate,
+    pub args: Vec<ArgTemplate>,
+    pub env: BTreeMap<String, ArgTemplate>,
+    pub cwd: Option<ArgTemplate>,
+    pub base: CommandSpec,
+}
+
+impl CommandTemplate {
+    pub fn new(program: impl Into<ArgTemplate>) -> Self {
+        CommandTemplate { program: program.into(), ..Default::default() }
+    }
+
+    /// Program and args as bash-like `${VAR}` templates, one per element:
+    /// `CommandTemplate::bash("git", &["commit", "-m", "${MSG}"])?`.
+    pub fn bash(program: &str, args: &[&str]) -> Result<Self, SyntaxError> {
+        let mut t = CommandTemplate::new(ArgTemplate::parse(program)?);
+        for a in args { t.args.push(ArgTemplate::parse(a)?); }
+        Ok(t)
+    }
+
+    pub fn arg(mut self, arg: impl Into<ArgTemplate>) -> Self { self.args.push(arg.into()); self }
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<ArgTemplate>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+    pub fn cwd(mut self, dir: impl Into<ArgTemplate>) -> Self { self.cwd = Some(dir.into()); self }
+    /// Take stdio, flags, criteria and other settings from `base`.
+    pub fn base(mut self, base: CommandSpec) -> Self { self.base = base; self }
+
+    /// Resolve every element into a validated `CommandSpec`. Base args come
+    /// before the templated ones.
+    pub fn instantiate<V: VariableResolver, F: FuncResolver>(&self, vars: &V, funcs: &F) -> Result<CommandSpec, SyntaxError> {
+        let mut cmd = self.base.clone();
+        cmd.program = self.program.render(vars, funcs)?;
+        for a in &self.args { cmd.args.push(a.render(vars, funcs)?); }
+        for (k, v) in &self.env { cmd.env.insert(k.clone(), v.render(vars, funcs)?); }
+        if let Some(d) = &self.cwd { cmd.cwd = Some(d.render(vars, funcs)?); }
+        cmd.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::PipelineSpec;
+    use crate::render::{PosixRenderer, Renderer};
+    use std::collections::HashMap;
+
+    struct Map(HashMap<&'static str, &'static str>);
+    impl VariableResolver for Map { fn get(&self, k: &str) -> Option<String> { self.0.get(k).map(|v| v.to_string()) } }
+    struct NoFunc;
+    impl FuncResolver for NoFunc { fn call(&self, _: &str, _: &[String]) -> Result<String, SyntaxError> { Ok(String::new()) } }
+
+    #[test]
+    fn each_value_lands_in_one_argv_element() {
+        let hostile = ["a b", "it's \"quoted\"", "x; rm -rf /", "$(id) `id` $HOME", "", "*"];
+        for value in hostile {
+            let vars = Map(HashMap::from([("MSG", value), ("DIR", "/tmp/my dir")]));
+            let t = CommandTemplate::bash("git", &["commit", "-m", "${MSG}", "--author=${MSG}"]).unwrap()
+                .env("NOTE", Template::parse("note: ${MSG}").unwrap())
+                .cwd(ArgTemplate::parse("${DIR}").unwrap());
+            let cmd = t.instantiate(&vars, &NoFunc).unwrap();
+            assert_eq!(cmd.args, vec!["commit".into(), "-m".into(), OsString::from(value), OsString::from(format!("--author={}", value))]);
+            assert_eq!(cmd.env["NOTE"], OsString::from(format!("note: {}", value)));
+            assert_eq!(cmd.cwd, Some("/tmp/my dir".into()));
+            // and the rendered shell line parses back to the same argv
+            let line = PosixRenderer::default().render_cmd(&cmd).unwrap();
+            assert_eq!(PipelineSpec::parse(&line).unwrap().stages[0], cmd, "{}", line);
+        }
+    }
+
+    #[test]
+    fn instantiate_keeps_base_and_validates() {
+        let base = CommandSpec::new("ignored").arg("--quiet").stdout_to("log").retries(1);
+        let t = CommandTemplate::new("rsync").arg(ArgTemplate::parse("${SRC}").unwrap()).arg("dst/").base(base);
+        let cmd = t.instantiate(&Map(HashMap::from([("SRC", "my files/")])), &NoFunc).unwrap();
+        assert_eq!(cmd.program, "rsync");
+        assert_eq!(cmd.args, vec!["--quiet", "my files/", "dst/"]);
+        assert_eq!(cmd.flags.retry.retries(), 1);
+
+        let nul = Map(HashMap::from([("SRC", "a\0b")]));
+        assert!(matches!(t.instantiate(&nul, &NoFunc), Err(SyntaxError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,268 @@
+//! Wrapping a command to run under `sudo`, `env`, `ssh` or `docker exec`.
+
+use std::ffi::OsString;
+
+use super::{CmdFlags, CommandSpec, PipelineSpec, SuccessCriteria};
+use crate::error::SyntaxError;
+use crate::render::{quote_sh, PosixRenderer, Renderer};
+
+/// A layer a command can be run through. [`apply`](Self::apply) turns a
+/// pipeline into a single new command; wrappers compose by applying them
+/// innermost first:
+///
+/// ```ignore
+/// let cmd = CommandSpec::new("id").arg("-u")
+///     .wrap(&Wrapper::sudo_user("deploy"))?
+///     .wrap(&Wrapper::ssh("web1"))?;
+/// ```
+///
+/// Where the layer takes a shell string (`sh -c`, `ssh`), the inner pipeline
+/// is rendered with a strict [`PosixRenderer`], so every layer adds exactly
+/// one level of quoting and each original argv element survives intact.
+/// `sudo` and `docker exec` pass a plain command (program and args only)
+/// through as argv and fall back to `sh -c` for anything else.
+///
+/// Executor-level settings of the last stage (timeout, retries, background,
+/// success criteria) move to the outer command, and secrets carry over so
+/// plans of the wrapped command still redact them. A secret containing `'`
+/// is re-quoted inside a shell string, so its quoted form is kept as a
+/// secret too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "kind", content = "value", rename_all = "snake_case"))]
+pub enum Wrapper {
+    /// `sh -c '<line>'`
+    Shell,
+    /// `env [-i] [-u K]… K=V… program args…`: the command's own environment
+    /// settings as `env(1)` arguments. Single commands only.
+    Env,
+    /// `sudo [-u user] -- …`
+    Sudo { user: Option<String> },
+    /// `ssh [opts…] -- host '<line>'`. The remote login shell must be
+    /// POSIX-compatible.
+    Ssh { host: String, opts: Vec<String> },
+    /// `docker exec [-u user] container …`
+    DockerExec { container: String, user: Option<String> },
+}
+
+impl Wrapper {
+    pub fn sudo() -> Self { Wrapper::Sudo { user: None } }
+    pub fn sudo_user(user: impl Into<String>) -> Self { Wrapper::Sudo { user: Some(user.into()) } }
+    pub fn ssh(host: impl Into<String>) -> Self { Wrapper::Ssh { host: host.into(), opts: Vec::new() } }
+    pub fn docker_exec(container: impl Into<String>) -> Self { Wrapper::DockerExec { container: container.into(), user: None } }
+
+    /// The command that runs `pipe` through this layer.
+    pub fn apply(&self, pipe: &PipelineSpec) -> Result<CommandSpec, SyntaxError> {
+        let Some(last) = pipe.stages.last() else {
+            return Err(SyntaxError::InvalidArgument("pipeline is empty".into()));
+        };
+        if let Wrapper::Env = self { return hoist_env(pipe); }
+
+        // settings the local executor applies stay outside the wrapper
+        let mut inner = pipe.clone();
+        let last_mut = inner.stages.last_mut().expect("non-empty");
+        let flags = CmdFlags { background: false, timeout_ms: None, retry: Default::default(), ..last.flags.clone() };
+        last_mut.flags = flags;
+        last_mut.success = SuccessCriteria::default();
+        let mut outer = CommandSpec {
+            flags: CmdFlags { background: last.flags.background, timeout_ms: last.flags.timeout_ms, retry: last.flags.retry.clone(), ..Default::default() },
+            success: last.success.clone(),
+            ..Default::default()
+        };
+        for c in &pipe.stages {
+            for s in &c.secrets {
+                outer = outer.secret(s.clone());
+                if let Some(q) = quote_sh(s).strip_prefix('\'').and_then(|q| q.strip_suffix('\'')) {
+                    outer = outer.secret(q);
+                }
+            }
+        }
+
+        let mut argv: Vec<OsString> = Vec::new();
+        match self {
+            Wrapper::Shell => {}
+            Wrapper::Sudo { user } => {
+                argv.push("sudo".into());
+                if let Some(u) = user { argv.extend(["-u".into(), u.into()]); }
+                argv.push("--".into());
+            }
+            Wrapper::Ssh { host, opts } => {
+                if host.is_empty() { return Err(SyntaxError::InvalidArgument("ssh host is empty".into())); }
+                argv.push("ssh".into());
+                argv.extend(opts.iter().map(OsString::from));
+                argv.extend(["--".into(), host.into(), shell_line(&inner)?]);
+                return Ok(outer.program_args(argv));
+            }
+            Wrapper::DockerExec { container, user } => {
+                if container.is_empty() || container.starts_with('-') {
+                    return Err(SyntaxError::InvalidArgument(format!("invalid container name {:?}", container)));
+                }
+                argv.push("docker".into());
+                argv.push("exec".into());
+                if let Some(u) = user { argv.extend(["-u".into(), u.into()]); }
+                argv.push(container.into());
+            }
+            Wrapper::Env => unreachable!(),
+        }
+        match plain_argv(&inner) {
+            Some(cmd) if !argv.is_empty() => argv.extend(cmd),
+            _ => argv.extend(["sh".into(), "-c".into(), shell_line(&inner)?]),
+        }
+        Ok(outer.program_args(argv))
+    }
+}
+
+impl CommandSpec {
+    /// Run this command through `wrapper` (see [`Wrapper`]).
+    pub fn wrap(&self, wrapper: &Wrapper) -> Result<CommandSpec, SyntaxError> { wrapper.apply(&self.clone().into()) }
+
+    fn program_args(mut self, mut argv: Vec<OsString>) -> Self {
+        self.program = argv.remove(0);
+        self.args = argv;
+        self
+    }
+}
+
+impl PipelineSpec {
+    /// Run this pipeline through `wrapper` (see [`Wrapper`]).
+    pub fn wrap(&self, wrapper: &Wrapper) -> Result<CommandSpec, SyntaxError> { wrapper.apply(self) }
+}
+
+fn shell_line(pipe: &PipelineSpec) -> Result<OsString, SyntaxError> {
+    for c in &pipe.stages { c.validate()?; }
+    Ok(PosixRenderer::default().render_pipe(pipe)?.into())
+}
+
+/// Program and args of a single command that needs nothing else.
+fn plain_argv(pipe: &PipelineSpec) -> Option<Vec<OsString>> {
+    let [c] = pipe.stages.as_slice() else { return None };
+    let bare = CommandSpec { program: c.program.clone(), args: c.args.clone(), secrets: c.secrets.clone(), ..Default::default() };
+    (*c == bare && pipe.opts == Default::default()).then(|| std::iter::once(c.program.clone()).chain(c.args.iter().cloned()).collect())
+}
+
+fn hoist_env(pipe: &PipelineSpec) -> Result<CommandSpec, SyntaxError> {
+    let [c] = pipe.stages.as_slice() else {
+        return Err(SyntaxError::InvalidArgument("env wrapper takes a single command".into()));
+    };
+    if !c.env_keep.is_empty() {
+        return Err(SyntaxError::InvalidArgument("env wrapper cannot keep inherited variables".into()));
+    }
+    // env(1) would take the program for another assignment
+    if c.program.to_string_lossy().contains('=') {
+        return Err(SyntaxError::InvalidArgument("env wrapper needs a program without '='".into()));
+    }
+    c.validate()?;
+    let mut argv: Vec<OsString> = vec!["env".into()];
+    if c.env_clear { argv.push("-i".into()); }
+    for k in &c.env_remove { argv.extend(["-u".into(), k.into()]); }
+    for (k, v) in &c.env {
+        let mut kv = OsString::from(format!("{}=", k));
+        kv.push(v);
+        argv.push(kv);
+    }
+    argv.push(c.program.clone());
+    argv.extend(c.args.iter().cloned());
+    let outer = CommandSpec { env: Default::default(), env_remove: Default::default(), env_clear: false, ..c.clone() };
+    Ok(outer.program_args(argv))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::time::Duration;
+
+    const HOSTILE: [&str; 8] = ["it's", "a b", "$(touch x)", "\"q\" `w`", "", "\\", "*", "line\nbreak"];
+
+    fn unwrap_sh_c(line: &OsStr) -> CommandSpec {
+        let c = CommandSpec::parse(line.to_str().unwrap()).unwrap();
+        assert_eq!(c.program, "sh");
+        assert_eq!(c.args[0], "-c");
+        CommandSpec::parse(c.args[1].to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn nested_wrappers_preserve_argv() {
+        let orig = CommandSpec::new("printf").arg("[%s]\n").args(HOSTILE).env("LANG", "C").cwd("/");
+        let ssh = orig.wrap(&Wrapper::sudo_user("root")).unwrap().wrap(&Wrapper::ssh("web1")).unwrap();
+        assert_eq!(ssh.program, "ssh");
+        assert_eq!(&ssh.args[..2], ["--", "web1"]);
+
+        // the remote shell parses the payload into the sudo command...
+        let sudo = CommandSpec::parse(ssh.args[2].to_str().unwrap()).unwrap();
+        assert_eq!(sudo.program, "sudo");
+        assert_eq!(&sudo.args[..3], ["-u", "root", "--"]);
+        // ...whose `sh -c` string parses back to the original command
+        assert_eq!(&sudo.args[3..5], ["sh", "-c"]);
+        assert_eq!(CommandSpec::parse(sudo.args[5].to_str().unwrap()).unwrap(), orig);
+
+        // an explicit `sh -c` layer adds one more level
+        let ssh = orig.wrap(&Wrapper::Shell).unwrap().wrap(&Wrapper::ssh("web1")).unwrap();
+        assert_eq!(unwrap_sh_c(&ssh.args[2]), orig);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nested_wrappers_run_under_sh() {
+        // remote shell parses the payload, sudo passes `sh -c` through as argv
+        let orig = CommandSpec::new("printf").arg("[%s]\n").args(HOSTILE).env("LANG", "C");
+        let ssh = orig.wrap(&Wrapper::Shell).unwrap().wrap(&Wrapper::sudo()).unwrap().wrap(&Wrapper::ssh("h")).unwrap();
+        let payload = ssh.args.last().unwrap().to_str().unwrap();
+        let dir = std::env::temp_dir().join(format!("sx-wrap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("sudo() {{ shift; \"$@\"; }}; {}", payload))
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        let expected: String = HOSTILE.iter().map(|a| format!("[{}]\n", a)).collect();
+        assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0, "nothing was expanded");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sudo_docker_and_env_forms() {
+        let plain = CommandSpec::new("id").arg("-u").timeout(Duration::from_secs(5)).success_statuses([0, 1]);
+        let sudo = plain.wrap(&Wrapper::sudo_user("deploy")).unwrap();
+        assert_eq!(sudo.program, "sudo");
+        assert_eq!(sudo.args, ["-u", "deploy", "--", "id", "-u"]);
+        assert_eq!(sudo.flags.timeout_ms, Some(5000));
+        assert!(sudo.success.accepts(1, b""));
+
+        let pipe = CommandSpec::new("ls").pipe(CommandSpec::new("wc").arg("-l")).pipefail();
+        let docker = pipe.wrap(&Wrapper::DockerExec { container: "app".into(), user: Some("www".into()) }).unwrap();
+        assert_eq!(docker.args, ["exec", "-u", "www", "app", "sh", "-c", "(set -o pipefail && ls | wc '-l')"]);
+        assert!(Wrapper::docker_exec("-it").apply(&pipe).is_err());
+
+        let env = CommandSpec::new("make").arg("all").env("CC", "clang").env_remove("MAKEFLAGS").env_clear().cwd("/src")
+            .wrap(&Wrapper::Env).unwrap();
+        assert_eq!(env.program, "env");
+        assert_eq!(env.args, ["-i", "-u", "MAKEFLAGS", "CC=clang", "make", "all"]);
+        assert!(env.env.is_empty() && !env.env_clear);
+        assert_eq!(env.cwd.as_deref(), Some(OsStr::new("/src")));
+        // sudo resets the environment; hoisting it keeps the argv form
+        let env = CommandSpec::new("make").env("CC", "clang").wrap(&Wrapper::Env).unwrap();
+        assert_eq!(env.wrap(&Wrapper::sudo()).unwrap().args, ["--", "env", "CC=clang", "make"]);
+        assert!(pipe.wrap(&Wrapper::Env).is_err());
+    }
+
+    #[test]
+    fn wrapped_plans_redact_secrets() {
+        let c = CommandSpec::new("curl").arg("-H").secret_arg("Authorization: Bearer s3cr3t");
+        let ssh = c.wrap(&Wrapper::sudo()).unwrap().wrap(&Wrapper::ssh("h")).unwrap();
+        let plan = PosixRenderer::default().render_cmd_plan(&ssh).unwrap();
+        assert!(!plan.contains("s3cr3t"), "{}", plan);
+        assert!(plan.contains("***"));
+
+        // each shell layer re-quotes the `'`
+        let c = CommandSpec::new("mysql").secret_arg("--password=it's\\ok").stdout_to("out");
+        for w in [Wrapper::Shell, Wrapper::ssh("h")] {
+            let twice = c.wrap(&Wrapper::sudo()).unwrap().wrap(&w).unwrap();
+            for r in [PosixRenderer::default(), PosixRenderer { bash: true, ..Default::default() }] {
+                let plan = r.render_cmd_plan(&twice).unwrap();
+                assert!(!plan.contains("it") && !plan.contains("ok") && plan.contains("***"), "{}", plan);
+            }
+        }
+    }
+}
@@ -6,6 +6,8 @@ pub enum SyntaxError {
     RenderError(String),
     ResolveError(String),
     ExecError(String),
+    /// A program could not be found on `PATH` (or at its given path).
+    ProgramNotFound(String),
 }
 
 impl fmt::Display for SyntaxError {
@@ -15,6 +17,7 @@ impl fmt::Display for SyntaxError {
             SyntaxError::RenderError(s) => write!(f, "Render error: {}", s),
             SyntaxError::ResolveError(s) => write!(f, "Resolve error: {}", s),
             SyntaxError::ExecError(s) => write!(f, "Exec error: {}", s),
+            SyntaxError::ProgramNotFound(s) => write!(f, "Program not found: {}", s),
         }
     }
 }
@@ -1,51 +1,516 @@
 //! Execution and planning interfaces.
 
-use crate::cmd::PipelineSpec;
+use std::time::Duration;
+
+use crate::cmd::{PipelineSpec, ScriptSpec};
 use crate::error::SyntaxError;
 use crate::render::Renderer;
 
+pub mod which;
+use which::ProgramResolver;
+
+/// Outcome of running a pipeline. Output is kept as raw bytes.
 #[derive(Debug, Clone, Default)]
 pub struct ExecResult {
+    /// The last stage's status, or under `pipefail` the rightmost non-zero one.
+    pub status: i32,
+    /// Whether the run met the last stage's `SuccessCriteria`.
+    pub success: bool,
+    /// Exit status of every stage, like bash's `PIPESTATUS`.
+    pub pipestatus: Vec<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Every run of the pipeline under its retry policy, oldest first. Output
+    /// is that of the final attempt.
+    pub attempts: Vec<Attempt>,
+}
+
+/// One run of a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attempt {
     pub status: i32,
-    pub stdout: String,
-    pub stderr: String,
+    pub elapsed: Duration,
+    /// Wait before the next attempt, if there was one.
+    pub delay: Option<Duration>,
+}
+
+impl ExecResult {
+    pub fn stdout_lossy(&self) -> String { String::from_utf8_lossy(&self.stdout).into_owned() }
+    pub fn stderr_lossy(&self) -> String { String::from_utf8_lossy(&self.stderr).into_owned() }
 }
 
 pub trait Executor {
+    /// Run a pipeline. Implementations should set `success` with [`classify`].
     fn exec(&self, pipe: &PipelineSpec) -> Result<ExecResult, SyntaxError>;
+
+    /// Run a command list with shell semantics: `&&`/`||` short-circuit on
+    /// success as classified by each pipeline's criteria, `;` always
+    /// continues. Output of every pipeline run is concatenated; status,
+    /// pipestatus and attempts are those of the last pipeline run.
+    fn exec_script(&self, script: &ScriptSpec) -> Result<ExecResult, SyntaxError> {
+        match script {
+            ScriptSpec::Pipe(p) => {
+                let mut r = self.exec(p)?;
+                r.success = classify(p, &r);
+                Ok(r)
+            }
+            ScriptSpec::Subshell(inner) | ScriptSpec::Group(inner) => self.exec_script(inner),
+            ScriptSpec::AndThen(a, b) | ScriptSpec::OrElse(a, b) | ScriptSpec::Always(a, b) => {
+                let first = self.exec_script(a)?;
+                let run_next = match script {
+                    ScriptSpec::AndThen(..) => first.success,
+                    ScriptSpec::OrElse(..) => !first.success,
+                    _ => true,
+                };
+                if !run_next { return Ok(first); }
+                let next = self.exec_script(b)?;
+                Ok(ExecResult {
+                    status: next.status,
+                    success: next.success,
+                    pipestatus: next.pipestatus,
+                    attempts: next.attempts,
+                    stdout: [first.stdout, next.stdout].concat(),
+                    stderr: [first.stderr, next.stderr].concat(),
+                })
+            }
+        }
+    }
+}
+
+/// Whether a result meets the success criteria of the pipeline's last stage.
+pub fn classify(pipe: &PipelineSpec, result: &ExecResult) -> bool {
+    pipe.stages.last().is_some_and(|c| c.success.accepts(result.status, &result.stdout))
 }
 
 /// A simple planner that renders a pipeline using the given renderer
 /// and returns the planned string (no execution).
-pub struct Planner<'a, R: Renderer> { pub renderer: &'a R }
+/// With a resolver, programs are shown as resolved paths and a missing tool
+/// fails the plan with `SyntaxError::ProgramNotFound`.
+pub struct Planner<'a, R: Renderer> {
+    pub renderer: &'a R,
+    pub resolver: Option<&'a dyn ProgramResolver>,
+}
 
 impl<'a, R: Renderer> Planner<'a, R> {
+    pub fn new(renderer: &'a R) -> Self { Planner { renderer, resolver: None } }
+    pub fn with_resolver(mut self, resolver: &'a dyn ProgramResolver) -> Self { self.resolver = Some(resolver); self }
+
     pub fn plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
-        self.renderer.render_pipe_plan(pipe)
+        match self.resolver {
+            Some(_) => self.renderer.render_pipe_plan(&self.resolve(pipe)?),
+            None => self.renderer.render_pipe_plan(pipe),
+        }
+    }
+
+    pub fn plan_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        match self.resolver {
+            Some(_) => self.renderer.render_script_plan(&script.try_map_pipes(&mut |p| self.resolve(p))?),
+            None => self.renderer.render_script_plan(script),
+        }
+    }
+
+    fn resolve(&self, pipe: &PipelineSpec) -> Result<PipelineSpec, SyntaxError> {
+        let mut out = pipe.clone();
+        if let Some(r) = self.resolver {
+            for c in &mut out.stages { c.program = r.resolve(c)?.into_os_string(); }
+        }
+        Ok(out)
     }
 }
 
+/// Spawns each pipeline stage directly with `std::process`, wiring stages
+/// together with OS pipes. Stdout of the last stage and stderr of every
+/// stage are captured unless redirected. A program that cannot be found or
+/// executed yields status 127/126 like the shell, rather than an error; the
+/// pipeline then does not run and `pipestatus` holds just that status.
+/// Failed pipelines are re-run per the last stage's retry policy.
 #[cfg(feature = "exec")]
 pub struct StdExecutor;
 
 #[cfg(feature = "exec")]
 impl Executor for StdExecutor {
     fn exec(&self, pipe: &PipelineSpec) -> Result<ExecResult, SyntaxError> {
-        use std::process::{Command, Stdio as PStdio};
-        use crate::render::{Renderer, PosixRenderer};
-        // Execute via shell using the safe-rendered pipeline string
-        let r = PosixRenderer::default();
-        let cmdline = r.render_pipe(pipe).map_err(|e| SyntaxError::ExecError(e.to_string()))?;
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(cmdline)
-            .stdin(PStdio::null())
-            .output()
-            .map_err(|e| SyntaxError::ExecError(e.to_string()))?;
-        Ok(ExecResult{
-            status: output.status.code().unwrap_or(-1),
-            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        let policy = pipe.stages.last().map(|c| c.flags.retry.clone()).unwrap_or_default();
+        let mut attempts: Vec<Attempt> = Vec::new();
+        loop {
+            let started = std::time::Instant::now();
+            let mut r = std_exec::run(pipe).map_err(|e| SyntaxError::ExecError(e.to_string()))?;
+            r.success = classify(pipe, &r);
+            let mut attempt = Attempt { status: r.status, elapsed: started.elapsed(), delay: None };
+            let n = attempts.len() as u32 + 1;
+            if r.success || n >= policy.max_attempts || !policy.should_retry(r.status, &r.stderr) {
+                attempts.push(attempt);
+                r.attempts = attempts;
+                return Ok(r);
+            }
+            let delay = policy.delay(n) + std_exec::jitter(policy.jitter_ms);
+            attempt.delay = Some(delay);
+            attempts.push(attempt);
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(feature = "exec")]
+mod std_exec {
+    use std::ffi::OsStr;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, PipeReader, PipeWriter, Read, Write};
+    use std::process::{Child, Command, Stdio as PStdio};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use super::ExecResult;
+    use crate::cmd::{CmdFlags, PipelineSpec, Stdio};
+
+    /// Where a stage's stdout/stderr goes.
+    enum Sink { Null, File(File), Pipe(PipeWriter) }
+
+    impl Sink {
+        fn try_clone(&self) -> io::Result<Sink> {
+            Ok(match self {
+                Sink::Null => Sink::Null,
+                Sink::File(f) => Sink::File(f.try_clone()?),
+                Sink::Pipe(w) => Sink::Pipe(w.try_clone()?),
+            })
+        }
+        fn into_stdio(self) -> PStdio {
+            match self {
+                Sink::Null => PStdio::null(),
+                Sink::File(f) => f.into(),
+                Sink::Pipe(w) => w.into(),
+            }
+        }
+    }
+
+    fn open_sink(path: &OsStr, append: bool) -> io::Result<Sink> {
+        let f = OpenOptions::new().write(true).create(true).append(append).truncate(!append).open(path)?;
+        Ok(Sink::File(f))
+    }
+
+    fn invalid(msg: String) -> io::Error { io::Error::new(io::ErrorKind::InvalidInput, msg) }
+
+    /// Random duration in `0..=max_ms`, seeded from std's per-map hash keys.
+    pub(super) fn jitter(max_ms: u64) -> Duration {
+        use std::hash::{BuildHasher, Hasher};
+        if max_ms == 0 { return Duration::ZERO; }
+        let r = std::collections::hash_map::RandomState::new().build_hasher().finish();
+        Duration::from_millis(r % (max_ms + 1))
+    }
+
+    fn drain(mut r: PipeReader) -> JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = r.read_to_end(&mut buf);
+            buf
+        })
+    }
+
+    pub(super) fn run(pipe: &PipelineSpec) -> io::Result<ExecResult> {
+        match run_stages(pipe) {
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied) => {
+                let status = if e.kind() == io::ErrorKind::NotFound { 127 } else { 126 };
+                Ok(ExecResult { status, pipestatus: vec![status], stderr: format!("{}\n", e).into_bytes(), ..Default::default() })
+            }
+            r => r,
+        }
+    }
+
+    fn run_stages(pipe: &PipelineSpec) -> io::Result<ExecResult> {
+        if pipe.is_empty() { return Err(invalid("empty pipeline".into())); }
+        let (out_r, out_w) = io::pipe()?;
+        let (err_r, err_w) = io::pipe()?;
+        let out_w = Sink::Pipe(out_w);
+        let err_w = Sink::Pipe(err_w);
+
+        let mut children: Vec<Child> = Vec::new();
+        let mut feeds: Vec<JoinHandle<()>> = Vec::new();
+        let mut prev: Option<PipeReader> = None;
+        let n = pipe.stages.len();
+        for (i, c) in pipe.effective_stages().map_err(|e| invalid(e.to_string()))?.enumerate() {
+            match spawn_stage(&c, prev.take(), i + 1 == n, &out_w, &err_w) {
+                Ok((child, next, feed)) => {
+                    children.push(child);
+                    prev = next;
+                    feeds.extend(feed);
+                }
+                Err(e) => {
+                    for mut ch in children { let _ = ch.kill(); let _ = ch.wait(); }
+                    return Err(e);
+                }
+            }
+        }
+        // Our copies of the capture pipes must be closed before draining.
+        drop(out_w);
+        drop(err_w);
+        let out_t = drain(out_r);
+        let err_t = drain(err_r);
+
+        let mut pipestatus = Vec::with_capacity(n);
+        for mut ch in children { pipestatus.push(ch.wait()?.code().unwrap_or(-1)); }
+        for f in feeds { let _ = f.join(); }
+        let last = pipestatus.last().copied().unwrap_or(-1);
+        let status = if pipe.opts.pipefail { pipestatus.iter().rev().copied().find(|s| *s != 0).unwrap_or(0) } else { last };
+        Ok(ExecResult {
+            status,
+            pipestatus,
+            stdout: out_t.join().unwrap_or_default(),
+            stderr: err_t.join().unwrap_or_default(),
+            ..Default::default()
         })
     }
+
+    type Spawned = (Child, Option<PipeReader>, Option<JoinHandle<()>>);
+
+    fn spawn_stage(c: &crate::cmd::CommandSpec, prev: Option<PipeReader>, last: bool, out_w: &Sink, err_w: &Sink) -> io::Result<Spawned> {
+        let mut cmd = Command::new(&c.program);
+        if c.env_clear {
+            cmd.env_clear();
+            for k in &c.env_keep {
+                if let Some(v) = std::env::var_os(k) { cmd.env(k, v); }
+            }
+        }
+        for k in &c.env_remove { cmd.env_remove(k); }
+        cmd.args(&c.args).envs(&c.env);
+        if let Some(dir) = &c.cwd { cmd.current_dir(dir); }
+        if c.flags.has_process_attrs() { set_process_attrs(&mut cmd, &c.flags)?; }
+
+        // stdin: previous stage, file, null or in-memory data
+        let feed = c.stdin.in_memory().map(|d| d.to_vec());
+        let stdin: PStdio = match &c.stdin {
+            Stdio::Inherit | Stdio::Pipe => match prev { Some(r) => r.into(), None => PStdio::null() },
+            Stdio::Null => PStdio::null(),
+            Stdio::File { path, .. } => File::open(path)?.into(),
+            Stdio::Bytes(_) | Stdio::Text(_) => PStdio::piped(),
+            Stdio::Dup(fd) => return Err(invalid(format!("unsupported fd duplication 0<&{}", fd))),
+        };
+
+        // stdout/stderr, applied left to right like the rendered `> … 2>&1`
+        let (next, out_default) = if last {
+            (None, out_w.try_clone()?)
+        } else {
+            let (r, w) = io::pipe()?;
+            (Some(r), Sink::Pipe(w))
+        };
+        let stdout = match &c.stdout {
+            Stdio::Inherit | Stdio::Pipe => out_default,
+            Stdio::Null => Sink::Null,
+            Stdio::File { path, append } => open_sink(path, *append)?,
+            Stdio::Dup(2) => err_w.try_clone()?,
+            Stdio::Dup(fd) => return Err(invalid(format!("unsupported fd duplication 1>&{}", fd))),
+            Stdio::Bytes(_) | Stdio::Text(_) => return Err(invalid("in-memory data is only valid for stdin".into())),
+        };
+        let stderr = match &c.stderr {
+            Stdio::Inherit | Stdio::Pipe => err_w.try_clone()?,
+            Stdio::Null => Sink::Null,
+            Stdio::File { path, append } => open_sink(path, *append)?,
+            Stdio::Dup(1) => stdout.try_clone()?,
+            Stdio::Dup(fd) => return Err(invalid(format!("unsupported fd duplication 2>&{}", fd))),
+            Stdio::Bytes(_) | Stdio::Text(_) => return Err(invalid("in-memory data is only valid for stdin".into())),
+        };
+        cmd.stdin(stdin).stdout(stdout.into_stdio()).stderr(stderr.into_stdio());
+
+        let mut child = cmd.spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", c.program.to_string_lossy(), e)))?;
+        // `cmd` holds parent copies of the pipe ends; drop it so readers see EOF.
+        drop(cmd);
+        let feed = match (feed, child.stdin.take()) {
+            (Some(data), Some(mut w)) => Some(thread::spawn(move || { let _ = w.write_all(&data); })),
+            _ => None,
+        };
+        Ok((child, next, feed))
+    }
+
+    /// Apply nice, umask and rlimits in the child between fork and exec.
+    #[cfg(target_os = "linux")]
+    fn set_process_attrs(cmd: &mut Command, flags: &CmdFlags) -> io::Result<()> {
+        use crate::cmd::Rlimit;
+        use std::os::unix::process::CommandExt;
+
+        let limits: Vec<_> = flags.rlimits.iter().map(|(limit, &v)| {
+            let res = match limit {
+                Rlimit::Cpu => libc::RLIMIT_CPU,
+                Rlimit::Memory => libc::RLIMIT_AS,
+                Rlimit::Data => libc::RLIMIT_DATA,
+                Rlimit::Stack => libc::RLIMIT_STACK,
+                Rlimit::OpenFiles => libc::RLIMIT_NOFILE,
+            };
+            let v = if v == u64::MAX { libc::RLIM_INFINITY } else { v as libc::rlim_t };
+            (res, libc::rlimit { rlim_cur: v, rlim_max: v })
+        }).collect();
+        let (nice, umask) = (flags.nice, flags.umask);
+        // SAFETY: the hook only calls async-signal-safe functions on data
+        // prepared before the fork and does not allocate.
+        unsafe {
+            cmd.pre_exec(move || {
+                for (res, lim) in &limits {
+                    if libc::setrlimit(*res, lim) != 0 { return Err(io::Error::last_os_error()); }
+                }
+                if let Some(m) = umask { libc::umask(m as libc::mode_t); }
+                if let Some(n) = nice {
+                    // nice(2) may legitimately return -1; only errno tells
+                    *libc::__errno_location() = 0;
+                    if libc::nice(n.into()) == -1 && *libc::__errno_location() != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_process_attrs(_cmd: &mut Command, _flags: &CmdFlags) -> io::Result<()> {
+        Err(invalid("nice/umask/rlimits are only supported on Linux".into()))
+    }
+}
+
+#[cfg(all(test, feature = "exec", unix))]
+mod tests {
+    use super::*;
+    use crate::cmd::{CommandSpec, ScriptSpec, Stdio};
+
+    fn sh(script: &str) -> CommandSpec {
+        CommandSpec { program: "sh".into(), args: vec!["-c".into(), script.into()], ..Default::default() }
+    }
+
+    #[test]
+    fn std_exec_pipeline_with_in_memory_stdin() {
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec { program: "cat".into(), stdin: Stdio::Text("it's $HOME\nSX_EOF\n".into()), ..Default::default() });
+        p.push(CommandSpec { program: "tr".into(), args: vec!["a-z".into(), "A-Z".into()], ..Default::default() });
+        let r = StdExecutor.exec(&p).unwrap();
+        assert_eq!(r.status, 0);
+        assert_eq!(r.stdout, b"IT'S $HOME\nSX_EOF\n");
+    }
+
+    #[test]
+    fn std_exec_script_short_circuits() {
+        let script = ScriptSpec::from(sh("echo a; exit 1"))
+            .and_then(sh("echo skipped"))
+            .or_else(sh("echo b"))
+            .then(sh("exit 4"));
+        let r = StdExecutor.exec_script(&script).unwrap();
+        assert_eq!((r.status, r.stdout_lossy().as_str()), (4, "a\nb\n"));
+
+        let missing = ScriptSpec::from(CommandSpec { program: "sx-no-such-tool".into(), ..Default::default() })
+            .or_else(sh("echo fallback"));
+        let r = StdExecutor.exec_script(&missing).unwrap();
+        assert_eq!(r.stdout, b"fallback\n");
+    }
+
+    #[test]
+    fn std_exec_env_clear_and_remove() {
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec::new("/usr/bin/env").env_clear().env_keep("PATH").env_keep("SX_UNSET_VAR").env("A", "1"));
+        let r = StdExecutor.exec(&p).unwrap();
+        let out = r.stdout_lossy();
+        let mut keys: Vec<&str> = out.lines().map(|l| l.split('=').next().unwrap()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["A", "PATH"]);
+
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec::new("sh").args(["-c", "echo ${HOME-unset}"]).env_remove("HOME"));
+        assert_eq!(StdExecutor.exec(&p).unwrap().stdout, b"unset\n");
+    }
+
+    #[test]
+    fn std_exec_passes_non_utf8_exactly() {
+        use std::os::unix::ffi::OsStringExt;
+        let raw = b"a\xff b\x80".to_vec();
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec::new("printf").arg("%s").arg(std::ffi::OsString::from_vec(raw.clone())));
+        assert_eq!(StdExecutor.exec(&p).unwrap().stdout, raw);
+    }
+
+    #[test]
+    fn std_exec_honours_fd_dups() {
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec { stderr: Stdio::Dup(1), ..sh("echo out; echo err >&2; exit 3") });
+        let r = StdExecutor.exec(&p).unwrap();
+        assert_eq!((r.status, r.stdout_lossy().as_str(), r.stderr_lossy().as_str()), (3, "out\nerr\n", ""));
+
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec { stdout: Stdio::Dup(2), ..sh("echo moved") });
+        let r = StdExecutor.exec(&p).unwrap();
+        assert_eq!((r.stdout_lossy().as_str(), r.stderr_lossy().as_str()), ("", "moved\n"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn std_exec_applies_process_attrs() {
+        use crate::cmd::Rlimit;
+        let probe = "ulimit -n; ulimit -t; umask; nice";
+        let base = StdExecutor.exec(&sh(probe).into()).unwrap().stdout_lossy();
+        let base_nice: i32 = base.lines().last().unwrap().parse().unwrap();
+        let cmd = sh(probe).rlimit(Rlimit::OpenFiles, 64).rlimit(Rlimit::Cpu, 30).umask(0o027).nice(3);
+        let r = StdExecutor.exec(&cmd.into()).unwrap();
+        assert_eq!(r.stdout_lossy(), format!("64\n30\n0027\n{}\n", (base_nice + 3).min(19)));
+    }
+
+    #[test]
+    fn std_exec_retries_per_policy() {
+        use crate::cmd::{Backoff, RetryPolicy};
+        let dir = std::env::temp_dir().join(format!("sx-retry-{}", std::process::id()));
+        let _ = std::fs::remove_file(&dir);
+        // fails with 75 until it has run three times
+        let flaky = sh("echo . >> \"$0\"; n=$(wc -l < \"$0\"); echo try$n >&2; [ $n -ge 3 ] || exit 75").arg(&dir);
+        let policy = RetryPolicy::attempts(5).backoff(Backoff::Fixed { delay_ms: 10 }).on_status(75);
+        let r = StdExecutor.exec(&flaky.clone().retry(policy.clone()).into()).unwrap();
+        let _ = std::fs::remove_file(&dir);
+        assert_eq!(r.status, 0);
+        assert_eq!(r.attempts.iter().map(|a| a.status).collect::<Vec<_>>(), vec![75, 75, 0]);
+        assert_eq!(r.attempts[0].delay, Some(Duration::from_millis(10)));
+        assert_eq!(r.attempts[2].delay, None);
+        assert_eq!(r.stderr_lossy(), "try3\n");
+
+        // statuses outside the policy are not retried
+        let r = StdExecutor.exec(&sh("exit 1").retry(policy).into()).unwrap();
+        assert_eq!((r.status, r.attempts.len()), (1, 1));
+    }
+
+    #[test]
+    fn std_exec_classifies_success() {
+        let script = ScriptSpec::from(sh("exit 1").success_statuses([0, 1]))
+            .and_then(sh("echo ran"))
+            .and_then(sh("true").stdout_non_empty())
+            .or_else(sh("echo empty"));
+        let r = StdExecutor.exec_script(&script).unwrap();
+        assert_eq!(r.stdout_lossy(), "ran\nempty\n");
+        assert!(r.success);
+
+        let r = StdExecutor.exec(&sh("echo nope").stdout_contains("yes").into()).unwrap();
+        assert_eq!((r.status, r.success), (0, false));
+    }
+
+    #[test]
+    fn exec_script_classifies_for_other_executors() {
+        // reports the program name as stdout and never sets `success`
+        struct Echo;
+        impl Executor for Echo {
+            fn exec(&self, pipe: &PipelineSpec) -> Result<ExecResult, SyntaxError> {
+                let c = &pipe.stages[0];
+                Ok(ExecResult { status: c.args.len() as i32, stdout: c.program.to_string_lossy().into_owned().into_bytes(), ..Default::default() })
+            }
+        }
+        let script = ScriptSpec::from(CommandSpec::new("a"))
+            .and_then(CommandSpec::new("b").arg("x").success_statuses([1]))
+            .and_then(CommandSpec::new("c").arg("x"))
+            .or_else(CommandSpec::new("d"));
+        let r = Echo.exec_script(&script).unwrap();
+        assert_eq!((r.stdout_lossy().as_ref(), r.success), ("abcd", true));
+    }
+
+    #[test]
+    fn std_exec_pipefail_merge_and_pipestatus() {
+        let p = sh("echo out; echo err >&2; exit 2").pipe(sh("cat; exit 3")).pipe(CommandSpec::new("cat"));
+        let r = StdExecutor.exec(&p).unwrap();
+        assert_eq!((r.status, r.pipestatus.as_slice()), (0, &[2, 3, 0][..]));
+        assert_eq!((r.stdout_lossy().as_str(), r.stderr_lossy().as_str()), ("out\n", "err\n"));
+
+        let r = StdExecutor.exec(&p.clone().pipefail().merge_stderr()).unwrap();
+        assert_eq!((r.status, r.pipestatus.as_slice()), (3, &[2, 3, 0][..]));
+        assert_eq!((r.stdout_lossy().as_str(), r.stderr_lossy().as_str()), ("out\nerr\n", ""));
+    }
 }
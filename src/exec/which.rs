@@ -0,0 +1,136 @@
+//! Program resolution: find the executable a `CommandSpec` would run.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::cmd::CommandSpec;
+use crate::error::SyntaxError;
+
+pub trait ProgramResolver {
+    /// Absolute path of the program `cmd` would run, or `ProgramNotFound`.
+    fn resolve(&self, cmd: &CommandSpec) -> Result<PathBuf, SyntaxError>;
+}
+
+/// Looks programs up like `execvp`, from the command's point of view:
+/// - aliases are applied first (`python` → `python3`, or to a fixed path);
+/// - a program with a path separator is taken relative to the command's cwd;
+/// - a bare name is searched in `PATH`: the command's own `PATH` env if it
+///   sets one, else `path`, else this process's `PATH`. Relative entries are
+///   relative to the command's cwd.
+///
+/// Only executable files match (on Windows, also `PATHEXT` extensions).
+#[derive(Debug, Clone, Default)]
+pub struct PathResolver {
+    pub aliases: BTreeMap<String, OsString>,
+    pub path: Option<OsString>,
+}
+
+impl PathResolver {
+    pub fn new() -> Self { Self::default() }
+    /// Resolve `name` as `target` (another name or a path).
+    pub fn alias(mut self, name: impl Into<String>, target: impl Into<OsString>) -> Self {
+        self.aliases.insert(name.into(), target.into());
+        self
+    }
+    /// Search this `PATH` instead of the process's.
+    pub fn path(mut self, path: impl Into<OsString>) -> Self { self.path = Some(path.into()); self }
+}
+
+impl ProgramResolver for PathResolver {
+    fn resolve(&self, cmd: &CommandSpec) -> Result<PathBuf, SyntaxError> {
+        let program = cmd.program.to_str().and_then(|p| self.aliases.get(p)).unwrap_or(&cmd.program);
+        let not_found = || SyntaxError::ProgramNotFound(program.to_string_lossy().into_owned());
+        if program.is_empty() { return Err(not_found()); }
+        let cwd = std::env::current_dir().map_err(|e| SyntaxError::ResolveError(e.to_string()))?;
+        let base = match &cmd.cwd { Some(d) => cwd.join(d), None => cwd };
+
+        let p = Path::new(program);
+        if p.is_absolute() || p.components().count() > 1 {
+            return executable(&base.join(p)).ok_or_else(not_found);
+        }
+        let search = cmd.env.get("PATH").cloned().or_else(|| self.path.clone()).or_else(|| std::env::var_os("PATH"));
+        for dir in std::env::split_paths(&search.unwrap_or_default()) {
+            if let Some(found) = executable(&base.join(dir).join(p)) { return Ok(found); }
+        }
+        Err(not_found())
+    }
+}
+
+/// Resolve a program name against this process's `PATH`.
+pub fn which(program: impl Into<OsString>) -> Result<PathBuf, SyntaxError> {
+    PathResolver::default().resolve(&CommandSpec::new(program))
+}
+
+fn executable(path: &Path) -> Option<PathBuf> {
+    candidates(path).into_iter().find(|c| is_executable(c))
+}
+
+#[cfg(unix)]
+fn is_executable(p: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(p).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(p: &Path) -> bool { p.is_file() }
+
+#[cfg(windows)]
+fn candidates(p: &Path) -> Vec<PathBuf> {
+    if p.extension().is_some() { return vec![p.to_path_buf()]; }
+    let exts = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    exts.split(';').filter(|e| !e.is_empty()).map(|e| p.with_extension(e.trim_start_matches('.'))).collect()
+}
+
+#[cfg(not(windows))]
+fn candidates(p: &Path) -> Vec<PathBuf> { vec![p.to_path_buf()] }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::exec::Planner;
+    use crate::render::PosixRenderer;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sx-which-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        for (file, mode) in [("bin/tool", 0o755), ("bin/data", 0o644)] {
+            std::fs::write(dir.join(file), "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(dir.join(file), std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn resolves_via_path_cwd_and_aliases() {
+        let dir = scratch("resolve");
+        let bin = dir.join("bin");
+        let r = PathResolver::new().path(&bin).alias("t", "tool").alias("local", "./bin/tool");
+
+        assert_eq!(r.resolve(&CommandSpec::new("tool")).unwrap(), bin.join("tool"));
+        assert_eq!(r.resolve(&CommandSpec::new("t")).unwrap(), bin.join("tool"));
+        // relative names and relative PATH entries follow the command's cwd
+        assert_eq!(r.resolve(&CommandSpec::new("local").cwd(&dir)).unwrap(), dir.join("./bin/tool"));
+        assert_eq!(PathResolver::new().resolve(&CommandSpec::new("tool").cwd(&dir).env("PATH", "bin")).unwrap(), dir.join("bin/tool"));
+        // not executable, not on PATH
+        assert!(matches!(r.resolve(&CommandSpec::new("data")), Err(SyntaxError::ProgramNotFound(p)) if p == "data"));
+        assert!(matches!(r.resolve(&CommandSpec::new("tool").env("PATH", "/nonexistent")), Err(SyntaxError::ProgramNotFound(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn planner_shows_resolved_paths() {
+        let dir = scratch("plan");
+        let bin = dir.join("bin");
+        let r = PathResolver::new().path(&bin);
+        let renderer = PosixRenderer::default();
+        let planner = Planner::new(&renderer).with_resolver(&r);
+        let plan = planner.plan(&CommandSpec::new("tool").arg("x").into()).unwrap();
+        assert_eq!(plan, format!("{}/tool 'x'", bin.display()));
+        let script = crate::cmd::ScriptSpec::from(CommandSpec::new("tool")).and_then(CommandSpec::new("sx-missing-tool"));
+        assert!(matches!(planner.plan_script(&script), Err(SyntaxError::ProgramNotFound(p)) if p == "sx-missing-tool"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
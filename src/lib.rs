@@ -9,5 +9,7 @@ pub mod lens;
 pub mod prelude;
 pub mod easy;
 pub mod macros;
+#[cfg(feature = "serde")]
+pub mod serial;
 
 pub use error::SyntaxError;
@@ -0,0 +1,202 @@
+//! Dockerfile `RUN`, `CMD` and `ENTRYPOINT` instructions.
+
+use super::{PosixRenderer, QuotePolicy, Renderer};
+use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec, Stdio};
+use crate::error::SyntaxError;
+use std::ffi::OsStr;
+
+/// Which Dockerfile instruction a [`DockerfileRenderer`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction { Run, Cmd, Entrypoint }
+
+impl Instruction {
+    fn keyword(self) -> &'static str {
+        match self { Instruction::Run => "RUN", Instruction::Cmd => "CMD", Instruction::Entrypoint => "ENTRYPOINT" }
+    }
+}
+
+/// Renders Dockerfile instructions. A single command with plain stdio is
+/// written in exec form, `RUN ["prog", "arg"]`, so no shell is involved;
+/// anything else (pipes, redirections, lists, retries, timeouts) falls back
+/// to shell form with a POSIX line as for [`PosixRenderer`].
+///
+/// A single command's `cwd` and `env` become `WORKDIR` and `ENV`
+/// instructions ahead of it. These stay in effect for every later
+/// instruction in the Dockerfile, and for the container at run time, so
+/// variables holding a secret are set on the shell-form line instead.
+/// Instructions cannot span lines, so here-docs and values containing
+/// newlines are rejected.
+#[derive(Debug, Clone)]
+pub struct DockerfileRenderer {
+    pub instruction: Instruction,
+    pub quote: QuotePolicy,
+}
+
+impl Default for DockerfileRenderer {
+    fn default() -> Self { Self { instruction: Instruction::Run, quote: QuotePolicy::Strict } }
+}
+
+impl Renderer for DockerfileRenderer {
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe(&cmd.clone().into()) }
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe_plan(&cmd.clone().into()) }
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> { self.render_script(&pipe.clone().into()) }
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> { self.render_script_plan(&pipe.clone().into()) }
+
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        match script {
+            ScriptSpec::Pipe(p) if p.stages.len() == 1 => self.render_single(p),
+            _ => self.shell_form(script),
+        }
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let redacted = script.try_map_pipes(&mut |p| {
+            Ok(PipelineSpec { stages: p.stages.iter().map(CommandSpec::redacted).collect(), ..p.clone() })
+        })?;
+        self.render_script(&redacted)
+    }
+}
+
+impl DockerfileRenderer {
+    /// Hoist `cwd` and `env` into `WORKDIR` and `ENV`, then write the rest in
+    /// exec form if it fits. With `env_clear` the variables stay on the line,
+    /// after the `env -i` that would otherwise drop them; so do secret ones.
+    fn render_single(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let mut cmd = pipe.stages[0].clone();
+        let mut out = String::new();
+        if let Some(dir) = cmd.cwd.take() {
+            out.push_str(&format!("WORKDIR {}\n", docker_word(&dir)?));
+        }
+        if !cmd.env_clear {
+            let (secret, plain) = std::mem::take(&mut cmd.env).into_iter().partition(|(_, v)| cmd.redact(v) != *v);
+            cmd.env = secret;
+            let mut vars = Vec::new();
+            for (k, v) in plain { vars.push(format!("{}={}", k, docker_word(&v)?)); }
+            if !vars.is_empty() { out.push_str(&format!("ENV {}\n", vars.join(" "))); }
+        }
+        let exec = matches!(cmd.stdin, Stdio::Inherit)
+            && matches!(cmd.stdout, Stdio::Inherit)
+            && matches!(cmd.stderr, Stdio::Inherit)
+            && !cmd.env_clear
+            && cmd.env.is_empty()
+            && cmd.env_remove.is_empty()
+            && !cmd.flags.has_process_attrs()
+            && cmd.flags.timeout_ms.is_none()
+            && cmd.flags.retry.retries() == 0
+            && !cmd.success.is_custom();
+        if exec {
+            let mut words = vec![json_str(&cmd.program)?];
+            for a in &cmd.args { words.push(json_str(a)?); }
+            out.push_str(&format!("{} [{}]", self.instruction.keyword(), words.join(", ")));
+        } else {
+            out.push_str(&self.shell_form(&PipelineSpec { stages: vec![cmd], ..pipe.clone() }.into())?);
+        }
+        Ok(out)
+    }
+
+    /// Docker has no executor of ours behind it, so retries, timeouts and
+    /// success criteria become shell code.
+    fn shell_form(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let posix = PosixRenderer { quote: self.quote, retry_loops: true, timeouts: true, ..Default::default() };
+        let line = posix.render_script(script)?;
+        if line.contains('\n') {
+            return Err(SyntaxError::RenderError("Dockerfile instructions cannot contain newlines or here-docs".into()));
+        }
+        Ok(format!("{} {}", self.instruction.keyword(), line))
+    }
+}
+
+fn utf8<'a>(s: &'a OsStr, what: &str) -> Result<&'a str, SyntaxError> {
+    s.to_str().ok_or_else(|| SyntaxError::RenderError(format!("{} must be valid UTF-8 in a Dockerfile: {:?}", what, s)))
+}
+
+/// A JSON string for exec form.
+fn json_str(s: &OsStr) -> Result<String, SyntaxError> {
+    let mut out = String::from("\"");
+    for c in utf8(s, "exec-form words")?.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(out)
+}
+
+/// A `WORKDIR` or `ENV` value: bare when it is made of safe characters,
+/// otherwise double-quoted with `\`, `"` and `$` escaped so Docker neither
+/// splits nor expands it.
+fn docker_word(s: &OsStr) -> Result<String, SyntaxError> {
+    let s = utf8(s, "ENV and WORKDIR values")?;
+    if s.contains(['\n', '\r']) {
+        return Err(SyntaxError::RenderError("Dockerfile instructions cannot contain newlines".into()));
+    }
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | ':' | '+' | ',' | '@' | '%')) {
+        return Ok(s.to_string());
+    }
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        if matches!(c, '\\' | '"' | '$') { out.push('\\'); }
+        out.push(c);
+    }
+    out.push('"');
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn exec_form_with_workdir_and_env() {
+        let cmd = CommandSpec::new("cargo").args(["build", "--features", "a b", r#"say "hi"\n"#, "tab\there"])
+            .cwd("/src/my app").env("RUSTFLAGS", "-D warnings").env("HOME", "$HOME/x").env("MODE", "release");
+        let out = DockerfileRenderer::default().render_cmd(&cmd).unwrap();
+        assert_eq!(out, concat!(
+            "WORKDIR \"/src/my app\"\n",
+            "ENV HOME=\"\\$HOME/x\" MODE=release RUSTFLAGS=\"-D warnings\"\n",
+            r#"RUN ["cargo", "build", "--features", "a b", "say \"hi\"\\n", "tab\there"]"#,
+        ));
+        let r = DockerfileRenderer { instruction: Instruction::Entrypoint, ..Default::default() };
+        assert_eq!(r.render_cmd(&CommandSpec::new("/bin/app").arg("\u{1}é")).unwrap(), r#"ENTRYPOINT ["/bin/app", "\u0001é"]"#);
+        let words: Vec<String> = serde_json::from_str(out.rsplit_once("RUN ").unwrap().1).unwrap();
+        let want: Vec<&str> = std::iter::once("cargo").chain(cmd.args.iter().map(|a| a.to_str().unwrap())).collect();
+        assert_eq!(words, want);
+    }
+
+    #[test]
+    fn shell_form_fallback() {
+        let r = DockerfileRenderer { instruction: Instruction::Cmd, quote: QuotePolicy::Loose };
+        let pipe = CommandSpec::new("grep").arg("x").cwd("/logs").pipe(CommandSpec::new("wc").arg("-l"));
+        assert_eq!(r.render_pipe(&pipe).unwrap(), "CMD (cd /logs && grep x) | wc -l");
+        let cmd = CommandSpec::new("make").env("V", "1").cwd("/src").stdout_to("build.log").timeout(Duration::from_secs(60));
+        assert_eq!(r.render_cmd(&cmd).unwrap(), "WORKDIR /src\nENV V=1\nCMD timeout 60 sh -c 'make > build.log'");
+        let script = ScriptSpec::from(CommandSpec::new("apt-get").arg("update")).and_then(CommandSpec::new("apt-get").args(["install", "-y", "curl"]));
+        assert_eq!(DockerfileRenderer::default().render_script(&script).unwrap(),
+            "RUN apt-get 'update' && apt-get 'install' '-y' 'curl'");
+        assert!(r.render_cmd(&CommandSpec::new("cat").stdin_text("x\n")).is_err());
+        assert_eq!(r.render_cmd(&CommandSpec::new("cat").stdin_text("x")).unwrap(), "CMD printf '%s' x | cat");
+        assert!(r.render_cmd(&CommandSpec::new("ls").cwd("a\nb")).is_err());
+    }
+
+    #[test]
+    fn plans_redact_secrets() {
+        let cmd = CommandSpec::new("login").secret_arg("hunter2").secret_env("TOKEN", "t0k");
+        let plan = DockerfileRenderer::default().render_cmd_plan(&cmd).unwrap();
+        assert!(!plan.contains("hunter2") && !plan.contains("t0k"), "{}", plan);
+    }
+
+    #[test]
+    fn secret_env_stays_out_of_env_instructions() {
+        let cmd = CommandSpec::new("login").env("USER", "me").secret_env("TOKEN", "t0k").env("URL", "https://x/?k=t0k").secret("t0k");
+        assert_eq!(DockerfileRenderer::default().render_cmd(&cmd).unwrap(),
+            "ENV USER=me\nRUN TOKEN='t0k' URL='https://x/?k=t0k' login");
+        assert_eq!(DockerfileRenderer::default().render_cmd(&CommandSpec::new("login").secret_env("TOKEN", "t0k")).unwrap(),
+            "RUN TOKEN='t0k' login");
+    }
+}
@@ -0,0 +1,221 @@
+//! fish rendering.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+
+use super::{is_simple_word, plan_meta, render_list, render_redir, stage_note, ulimit_arg, with_notes, ListSyntax, QuotePolicy, Renderer};
+use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec};
+use crate::error::SyntaxError;
+
+// `&&`/`||` need fish 3.0. fish has no subshells: both kinds of group are
+// `begin; …; end`, which does not isolate `cd`.
+const FISH_LIST: ListSyntax = ListSyntax {
+    and: " && ", or: " || ", seq: "; ",
+    subshell: ("begin; ", "; end"), group: ("begin; ", "; end"),
+    group_all: false,
+};
+
+/// Renders for fish 3. Values are single-quoted (only `\` and `'` are
+/// escaped inside) and bytes that are not UTF-8 become `\XHH`.
+///
+/// fish has no `VAR=x cmd` prefix before 3.1 and no here-docs, so env goes
+/// through `env VAR=x cmd` and in-memory stdin is fed by `printf '%s' … |`.
+/// Commands whose `cd`, `umask` or `ulimit` must not leak (inside pipelines
+/// and lists, or always for umask/ulimit) run under `fish -c`.
+#[derive(Debug, Clone)]
+pub struct FishRenderer {
+    /// Ignored: fish values are always single-quoted.
+    #[deprecated(note = "has no effect; fish values are always single-quoted")]
+    pub quote: QuotePolicy,
+}
+
+#[allow(deprecated)]
+impl Default for FishRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+
+impl Renderer for FishRenderer {
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        self.render_line(cmd, false)
+    }
+
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        Ok(format!("{}{}", self.render_line(&cmd.redacted(), false)?, plan_meta(cmd, false)))
+    }
+
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        self.render_pipe_line(pipe, false, false, &mut Vec::new())
+    }
+
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let s = self.render_pipe_line(pipe, true, false, &mut notes)?;
+        Ok(with_notes(s, notes))
+    }
+
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let (s, _) = render_list(script, &FISH_LIST, &mut |p| Ok((self.render_pipe_line(p, false, true, &mut Vec::new())?, Vec::new())))?;
+        Ok(s)
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let (s, _) = render_list(script, &FISH_LIST, &mut |p| Ok((self.render_pipe_line(p, true, true, &mut notes)?, Vec::new())))?;
+        Ok(with_notes(s, notes))
+    }
+}
+
+impl FishRenderer {
+    /// fish has no `pipefail`; `$pipestatus` is left to the caller. Plans
+    /// collect each stage's plan notes in `notes`.
+    fn render_pipe_line(&self, pipe: &PipelineSpec, plan: bool, in_list: bool, notes: &mut Vec<String>) -> Result<String, SyntaxError> {
+        if pipe.opts.pipefail && pipe.stages.len() > 1 {
+            return Err(SyntaxError::RenderError("pipefail is not supported by fish".into()));
+        }
+        if !plan && in_list && pipe.stages.last().is_some_and(|c| c.success.is_custom()) {
+            return Err(SyntaxError::RenderError("custom success criteria cannot be rendered for fish lists".into()));
+        }
+        let isolate = in_list || pipe.stages.len() > 1;
+        let mut parts = Vec::new();
+        for c in pipe.effective_stages()? {
+            let c = if plan { Cow::Owned(c.redacted()) } else { c };
+            if plan { notes.extend(stage_note(&c, false)); }
+            parts.push(self.render_line(&c, isolate)?);
+        }
+        let mut s = parts.join(" | ");
+        if plan {
+            if let Some(last) = pipe.stages.last() { if last.flags.background { s.push_str(" &"); } }
+        }
+        Ok(s)
+    }
+
+    fn render_line(&self, cmd: &CommandSpec, isolate: bool) -> Result<String, SyntaxError> {
+        if cmd.program.is_empty() {
+            return Err(SyntaxError::RenderError("program empty".into()));
+        }
+        let mut parts: Vec<String> = Vec::new();
+
+        let via_env = cmd.env_clear || !cmd.env_remove.is_empty() || !cmd.env.is_empty();
+        if via_env {
+            if cmd.program.as_encoded_bytes().contains(&b'=') {
+                return Err(SyntaxError::RenderError("program containing '=' cannot be run via env".into()));
+            }
+            parts.push("env".to_string());
+            if cmd.env_clear { parts.push("-i".to_string()); }
+            // options first: env stops parsing them at the first assignment
+            for k in &cmd.env_remove { parts.push(format!("-u {}", k)); }
+            // expands to nothing when K is unset; split0 keeps newlines in one element
+            for k in cmd.env_keep.iter().filter(|_| cmd.env_clear) {
+                parts.push(format!("(set -q {k}; and string join0 -- \"{k}=${k}\" | string split0)", k = k));
+            }
+            for (k, v) in &cmd.env { parts.push(format!("{}={}", k, quote_fish(v))); }
+        }
+        if let Some(n) = cmd.flags.nice { parts.insert(0, format!("nice -n {}", n)); }
+
+        parts.push(quote_prog_fish(&cmd.program));
+        for a in &cmd.args { parts.push(quote_fish(a)); }
+
+        let feed = match cmd.stdin.in_memory() {
+            Some([]) => { parts.push("< /dev/null".to_string()); None }
+            Some(data) => {
+                if data.contains(&0) { return Err(SyntaxError::RenderError("printf stdin contains a NUL byte".into())); }
+                Some(format!("printf '%s' {} | ", quote_fish_bytes(data)))
+            }
+            None => { if let Some(r) = render_redir(0, &cmd.stdin, quote_fish)? { parts.push(r); } None }
+        };
+        if let Some(r) = render_redir(1, &cmd.stdout, quote_fish)? { parts.push(r); }
+        if let Some(r) = render_redir(2, &cmd.stderr, quote_fish)? { parts.push(r); }
+        let mut line = format!("{}{}", feed.unwrap_or_default(), parts.join(" "));
+
+        let mut prelude: Vec<String> = Vec::new();
+        for (&limit, &v) in &cmd.flags.rlimits { prelude.push(format!("ulimit {}", ulimit_arg(limit, v))); }
+        if let Some(m) = cmd.flags.umask { prelude.push(format!("umask {:03o}", m)); }
+        let scoped = !prelude.is_empty();
+        if let Some(dir) = &cmd.cwd { prelude.push(format!("cd {}", quote_fish(dir))); }
+        if !prelude.is_empty() {
+            prelude.push(line);
+            line = prelude.join(" && ");
+            if isolate || scoped { line = format!("fish -c {}", quote_fish(OsStr::new(&line))); }
+        }
+        Ok(line)
+    }
+}
+
+fn quote_fish(s: &OsStr) -> String { quote_fish_bytes(s.as_encoded_bytes()) }
+
+/// Single-quote bytes for fish; invalid UTF-8 is written as adjacent `\XHH`.
+fn quote_fish_bytes(bytes: &[u8]) -> String {
+    if bytes.is_empty() { return "''".to_string(); }
+    let mut out = String::new();
+    for chunk in bytes.utf8_chunks() {
+        if !chunk.valid().is_empty() {
+            out.push('\'');
+            for ch in chunk.valid().chars() {
+                if matches!(ch, '\'' | '\\') { out.push('\\'); }
+                out.push(ch);
+            }
+            out.push('\'');
+        }
+        for b in chunk.invalid() { let _ = write!(out, "\\X{:02x}", b); }
+    }
+    out
+}
+
+fn quote_prog_fish(p: &OsStr) -> String {
+    // a bare `a=b` would be an assignment (fish 3.1+)
+    match p.to_str() {
+        Some(s) if is_simple_word(s) && !s.contains(['=', '%']) => s.to_string(),
+        _ => quote_fish(p),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{Rlimit, Stdio};
+
+    #[test]
+    fn fish_render_quoting_env_and_redirs() {
+        let r = FishRenderer::default();
+        let cmd = CommandSpec::new("grep").args(["it's", "a\\b", "$HOME (x) *"]).env("LANG", "C").cwd("/w d")
+            .stdin_from("in.txt").stdout_append("out file").stderr_to_stdout();
+        assert_eq!(r.render_cmd(&cmd).unwrap(),
+            r"cd '/w d' && env LANG='C' grep 'it\'s' 'a\\b' '$HOME (x) *' < 'in.txt' >> 'out file' 2>&1");
+        // inside a pipeline the `cd` must not leak into the next stage
+        let pipe = cmd.clone().pipe(CommandSpec::new("wc"));
+        assert_eq!(r.render_pipe(&pipe).unwrap(),
+            r"fish -c 'cd \'/w d\' && env LANG=\'C\' grep \'it\\\'s\' \'a\\\\b\' \'$HOME (x) *\' < \'in.txt\' >> \'out file\' 2>&1' | wc");
+
+        let hermetic = CommandSpec::new("cargo").arg("build").env_clear().env_keep("PATH").env_remove("X");
+        assert_eq!(r.render_cmd(&hermetic).unwrap(),
+            "env -i -u X (set -q PATH; and string join0 -- \"PATH=$PATH\" | string split0) cargo 'build'");
+        let limited = CommandSpec::new("make").nice(5).umask(0o27).rlimit(Rlimit::OpenFiles, 64);
+        assert_eq!(r.render_cmd(&limited).unwrap(), "fish -c 'ulimit -n 64 && umask 027 && nice -n 5 make'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fish_render_stdin_and_lists() {
+        use std::os::unix::ffi::OsStrExt;
+        let r = FishRenderer::default();
+        let feed = CommandSpec::new("cat").stdin_text("it's").pipe(CommandSpec::new("sort").arg(OsStr::from_bytes(b"caf\xe9")));
+        assert_eq!(r.render_pipe(&feed).unwrap(), r"printf '%s' 'it\'s' | cat | sort 'caf'\Xe9");
+        assert!(r.render_pipe(&feed.pipefail()).is_err());
+
+        // `\X` escapes are quoted again, and so kept as bytes, inside `fish -c`
+        let scoped = CommandSpec::new("sort").arg(OsStr::from_bytes(b"caf\xe9")).cwd("/w").stdin(Stdio::Bytes(b"\xff'".to_vec()));
+        assert_eq!(r.render_pipe(&CommandSpec::new("ls").pipe(scoped)).unwrap(),
+            r"ls | fish -c 'cd \'/w\' && printf \'%s\' \\Xff\'\\\'\' | sort \'caf\'\\Xe9'");
+
+        // `begin; …; end` does not scope `cd`, so a list runs it under `fish -c` too
+        let c = |p: &str| CommandSpec::new(p);
+        let script = ScriptSpec::from(c("make").cwd("sub")).and_then(c("ls")).group().then(c("pwd"));
+        assert_eq!(r.render_script(&script).unwrap(), r"begin; fish -c 'cd \'sub\' && make' && ls; end; pwd");
+        assert_eq!(r.render_cmd(&c("make").cwd("sub")).unwrap(), "cd 'sub' && make");
+        let plan = ScriptSpec::from(c("deploy").secret_arg("s3cr3t").background());
+        assert_eq!(r.render_script_plan(&plan).unwrap(), "deploy '***' &");
+        // notes come last, so the plan still runs as pasted
+        let timed = c("a").timeout(std::time::Duration::from_secs(1));
+        assert_eq!(r.render_script_plan(&ScriptSpec::from(timed.clone()).and_then(c("b"))).unwrap(), "a && b  # a: timeout=1000ms");
+        assert_eq!(r.render_pipe_plan(&timed.clone().pipe(c("b"))).unwrap(), "a | b  # a: timeout=1000ms");
+        assert_eq!(r.render_pipe_plan(&timed.background().into()).unwrap(), "a &  # a: timeout=1000ms");
+    }
+}
@@ -1,176 +1,781 @@
 //! Rendering strategies for commands and templates.
 
-use crate::cmd::{CommandSpec, PipelineSpec, Stdio};
+use crate::cmd::{CommandSpec, PipelineSpec, RetryPolicy, Rlimit, ScriptSpec, Stdio, SuccessCriteria};
 use crate::error::SyntaxError;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
 
+mod docker;
+mod fish;
+mod nu;
+mod powershell;
+mod script;
+mod task;
+
+pub use docker::{DockerfileRenderer, Instruction};
+pub use fish::FishRenderer;
+pub use nu::NuRenderer;
+pub use powershell::PowerShellRenderer;
+pub use script::{ScriptFile, Step};
+pub use task::{JustRenderer, MakeRenderer};
+
+/// How `PosixRenderer` quotes values. `Strict` single-quotes every value;
+/// `Loose` leaves words of safe characters bare (`echo a`) and quotes the
+/// rest exactly as `Strict` would, so both give the shell the same argv.
+/// cmd.exe lines are always quoted minimally; the other renderers always
+/// quote, and the `quote` fields of `WinRenderer`, `PowerShellRenderer`,
+/// `FishRenderer` and `NuRenderer` are deprecated and ignored.
 #[derive(Debug, Clone, Copy)]
 pub enum QuotePolicy { Strict, Loose }
 
+/// Renders specs for one shell or file format.
+///
+/// What a target cannot express is a `RenderError`, never a line that runs
+/// differently. Only POSIX shells report the status of every pipeline
+/// stage, so elsewhere `pipefail` is rejected; targets that cannot set
+/// nice, umask or rlimits reject them too, and plans only show them.
 pub trait Renderer {
     fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError>;
     fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
         let mut parts = Vec::new();
-        for c in &pipe.0 { parts.push(self.render_cmd(c)?); }
+        for c in pipe.effective_stages()? { parts.push(self.render_cmd(&c)?); }
         Ok(parts.join(" | "))
     }
-    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_cmd(cmd) }
+    /// Like `render_cmd`, with secret values replaced by `cmd::REDACTED`.
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_cmd(&cmd.redacted()) }
     fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
         let mut parts = Vec::new();
-        for c in &pipe.0 { parts.push(self.render_cmd_plan(c)?); }
+        for c in pipe.effective_stages()? { parts.push(self.render_cmd_plan(&c)?); }
         Ok(parts.join(" | "))
     }
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let (s, _) = render_list(script, &POSIX_LIST, &mut |p| Ok((self.render_pipe(p)?, Vec::new())))?;
+        Ok(s)
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let (s, _) = render_list(script, &POSIX_LIST, &mut |p| Ok((self.render_pipe_plan(p)?, Vec::new())))?;
+        Ok(s)
+    }
+}
+
+/// Spelling of list operators used when rendering a `ScriptSpec`.
+struct ListSyntax {
+    and: &'static str,
+    or: &'static str,
+    seq: &'static str,
+    subshell: (&'static str, &'static str),
+    group: (&'static str, &'static str),
+    /// Bracket every compound operand instead of relying on precedence.
+    group_all: bool,
+}
+
+const POSIX_LIST: ListSyntax = ListSyntax {
+    and: " && ", or: " || ", seq: "; ",
+    subshell: ("( ", " )"), group: ("{ ", "; }"),
+    group_all: false,
+};
+
+// Pretty plans keep a list one logical line, so here-doc bodies can still
+// follow it.
+const POSIX_PRETTY_LIST: ListSyntax = ListSyntax {
+    and: " \\\n  && ", or: " \\\n  || ", seq: "; \\\n",
+    subshell: ("( ", " )"), group: ("{ ", "; }"),
+    group_all: false,
+};
+
+// cmd.exe has no subshells or brace groups; both become `( … )`.
+const WIN_LIST: ListSyntax = ListSyntax {
+    and: " && ", or: " || ", seq: " & ",
+    subshell: ("(", ")"), group: ("(", ")"),
+    group_all: true,
+};
+
+const WIN_PRETTY_LIST: ListSyntax = ListSyntax {
+    and: " ^\n  && ", or: " ^\n  || ", seq: " ^\n  & ",
+    subshell: ("(", ")"), group: ("(", ")"),
+    group_all: true,
+};
+
+type PipeLine = (String, Vec<String>);
+
+/// Render a list with `render_pipe` supplying each pipeline line plus any
+/// here-doc bodies, which are collected so they can follow the whole line.
+/// Only a script that is a single pipeline may run in the background; `a &`
+/// cannot be an operand of `&&` or sit inside a group.
+fn render_list<F>(script: &ScriptSpec, syn: &ListSyntax, render_pipe: &mut F) -> Result<PipeLine, SyntaxError>
+where F: FnMut(&PipelineSpec) -> Result<PipeLine, SyntaxError> {
+    fn compound(s: &ScriptSpec) -> bool {
+        matches!(s, ScriptSpec::AndThen(..) | ScriptSpec::OrElse(..) | ScriptSpec::Always(..))
+    }
+    fn background(s: &ScriptSpec) -> Result<(), SyntaxError> {
+        match s {
+            ScriptSpec::Pipe(p) if p.stages.last().is_some_and(|c| c.flags.background) => {
+                Err(SyntaxError::RenderError("a background pipeline cannot be part of a list".into()))
+            }
+            _ => Ok(()),
+        }
+    }
+    let (op, a, b) = match script {
+        ScriptSpec::Pipe(p) => {
+            if p.is_empty() { return Err(SyntaxError::RenderError("empty pipeline in script".into())); }
+            return render_pipe(p);
+        }
+        ScriptSpec::Subshell(inner) | ScriptSpec::Group(inner) => {
+            background(inner)?;
+            let (open, close) = if matches!(script, ScriptSpec::Subshell(_)) { syn.subshell } else { syn.group };
+            let (s, docs) = render_list(inner, syn, render_pipe)?;
+            return Ok((format!("{}{}{}", open, s, close), docs));
+        }
+        ScriptSpec::AndThen(a, b) => (syn.and, a, b),
+        ScriptSpec::OrElse(a, b) => (syn.or, a, b),
+        ScriptSpec::Always(a, b) => (syn.seq, a, b),
+    };
+    background(a)?;
+    background(b)?;
+    let seq = matches!(script, ScriptSpec::Always(..));
+    let (mut left, mut docs) = render_list(a, syn, render_pipe)?;
+    let (mut right, more) = render_list(b, syn, render_pipe)?;
+    docs.extend(more);
+    // `&&`/`||` are left-associative and bind tighter than `;`
+    if compound(a) && (syn.group_all || (!seq && matches!(**a, ScriptSpec::Always(..)))) {
+        left = format!("{}{}{}", syn.group.0, left, syn.group.1);
+    }
+    if compound(b) && (syn.group_all || !seq) {
+        right = format!("{}{}{}", syn.group.0, right, syn.group.1);
+    }
+    Ok((format!("{}{}{}", left, op, right), docs))
 }
 
 #[derive(Debug, Clone)]
-pub struct PosixRenderer { pub quote: QuotePolicy }
+pub struct PosixRenderer {
+    pub quote: QuotePolicy,
+    /// Render retry policies as shell loops instead of leaving retries to the
+    /// executor. Plans keep the `# retries=N` annotation.
+    pub retry_loops: bool,
+    /// Run pipelines with a timeout under coreutils/busybox `timeout`:
+    /// `timeout 1.5 sh -c 'LINE'` (status 124 when it expires). Plans keep
+    /// the `# timeout=…` annotation.
+    pub timeouts: bool,
+    /// Target bash, ksh93 or zsh: values with control characters or bytes
+    /// that are not UTF-8 are written as ANSI-C `$'…'` strings (`\n`, `\t`,
+    /// `\xNN`) and in-memory stdin as a `<<<` here-string, so every line
+    /// stays on one line. Other values are single-quoted as usual.
+    pub bash: bool,
+    /// Lay plans out for review: each pipeline stage and list operand starts
+    /// a line, arguments wrap at this many columns (`--flag value` pairs kept
+    /// together) and lines end in ` \` so the plan still runs as pasted.
+    /// Plan notes for all stages go in one comment at the end.
+    pub width: Option<usize>,
+}
 
-impl Default for PosixRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+impl Default for PosixRenderer {
+    fn default() -> Self { Self { quote: QuotePolicy::Strict, retry_loops: false, timeouts: false, bash: false, width: None } }
+}
 
 impl Renderer for PosixRenderer {
-    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+    /// Rendered as a one-stage pipeline, so retry loops and timeouts apply.
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe(&cmd.clone().into()) }
+
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let (line, docs) = self.render_pipe_line(pipe, false, false, &mut Vec::new())?;
+        Ok(attach_heredocs(line, docs))
+    }
+
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        let (mut s, doc) = self.render_line(&cmd.redacted(), false, self.width.map(|_| 0))?;
+        s.push_str(&plan_meta(cmd, false));
+        Ok(attach_heredocs(s, doc.into_iter().collect()))
+    }
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let (line, docs) = self.render_pipe_line(pipe, true, false, &mut notes)?;
+        Ok(attach_heredocs(with_notes(line, notes), docs))
+    }
+
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let (line, docs) = render_list(script, &POSIX_LIST, &mut |p| self.render_pipe_line(p, false, true, &mut Vec::new()))?;
+        Ok(attach_heredocs(line, docs))
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let syn = if self.width.is_some() { &POSIX_PRETTY_LIST } else { &POSIX_LIST };
+        let mut notes = Vec::new();
+        let (line, docs) = render_list(script, syn, &mut |p| self.render_pipe_line(p, true, true, &mut notes))?;
+        Ok(attach_heredocs(with_notes(line, notes), docs))
+    }
+}
+
+impl PosixRenderer {
+    /// Render a pipeline line, returning here-doc bodies separately. Inside
+    /// pipelines and lists a stage's `cd` is isolated in a subshell so it
+    /// does not apply to its neighbours. `pipefail` scopes
+    /// `set -o pipefail` to a subshell around the pipeline. Pretty plans and
+    /// lists collect each stage's plan notes in `notes` instead of appending
+    /// them, as a `#` would comment out the rest of the list.
+    fn render_pipe_line(&self, pipe: &PipelineSpec, plan: bool, in_list: bool, notes: &mut Vec<String>) -> Result<PipeLine, SyntaxError> {
+        let isolate = in_list || pipe.stages.len() > 1;
+        let pretty = plan && self.width.is_some();
+        let mut parts = Vec::new();
+        let mut docs = Vec::new();
+        for (i, c) in pipe.effective_stages()?.enumerate() {
+            let c = if plan { Cow::Owned(c.redacted()) } else { c };
+            // later stages start after `  | `
+            let (mut line, doc) = self.render_line(&c, isolate, pretty.then_some(if i == 0 { 0 } else { 4 }))?;
+            if pretty || (plan && in_list) { notes.extend(stage_note(&c, false)); } else if plan { line.push_str(&plan_meta(&c, false)); }
+            parts.push(line);
+            docs.extend(doc);
+        }
+        let mut s = parts.join(if pretty { " \\\n  | " } else { " | " });
+        if pipe.opts.pipefail && pipe.stages.len() > 1 {
+            s = format!("(set -o pipefail && {})", s);
+        }
+        if let (false, Some(last)) = (plan, pipe.stages.last()) {
+            // here-doc bodies go inside the quoted line
+            if let Some(ms) = last.flags.timeout_ms.filter(|_| self.timeouts) {
+                let line = attach_heredocs(s, std::mem::take(&mut docs));
+                s = if self.bash {
+                    format!("timeout {} bash -c {}", secs(ms), quote_bash(OsStr::new(&line)))
+                } else {
+                    format!("timeout {} sh -c {}", secs(ms), quote_sh(line))
+                };
+            }
+            let looped = self.retry_loops && last.flags.retry.retries() > 0;
+            // the status only matters to a surrounding list or retry loop
+            if last.success.is_custom() && (in_list || looped) { s = judge_status(&s, &last.success)?; }
+            if looped { s = retry_loop(&s, &last.flags.retry)?; }
+        }
+        if plan {
+            if let Some(last) = pipe.stages.last() { if last.flags.background { s.push_str(" &"); } }
+        }
+        Ok((s, docs))
+    }
+
+    /// Render a single command line. In-memory stdin becomes a `<<'DELIM'`
+    /// redirection and its here-doc body is returned separately, since the
+    /// body has to follow the complete line (including any later pipe stages).
+    /// `pretty` is the column a pretty plan line starts at.
+    fn render_line(&self, cmd: &CommandSpec, isolate: bool, pretty: Option<usize>) -> Result<(String, Option<String>), SyntaxError> {
         if cmd.program.is_empty() {
             return Err(SyntaxError::RenderError("program empty".into()));
         }
+        let quote: fn(&OsStr) -> String = match (self.quote, self.bash) {
+            (QuotePolicy::Strict, false) => |s| quote_sh(s),
+            (QuotePolicy::Strict, true) => quote_bash,
+            (QuotePolicy::Loose, false) => |s| bare_word(s).unwrap_or_else(|| quote_sh(s)),
+            (QuotePolicy::Loose, true) => |s| bare_word(s).unwrap_or_else(|| quote_bash(s)),
+        };
 
         // Compose core command with env + program + args + redirections
         let mut parts: Vec<String> = Vec::new();
 
+        // env removal/clearing goes through `env -i`/`env -u`
+        let via_env = cmd.env_clear || !cmd.env_remove.is_empty();
+        if via_env {
+            if cmd.program.as_encoded_bytes().contains(&b'=') {
+                return Err(SyntaxError::RenderError("program containing '=' cannot be run via env".into()));
+            }
+            parts.push("env".to_string());
+            if cmd.env_clear {
+                parts.push("-i".to_string());
+                // `${K+"K=$K"}` passes K through only if it is set
+                for k in &cmd.env_keep { parts.push(format!("${{{k}+\"{k}=${k}\"}}", k = k)); }
+            }
+            for k in &cmd.env_remove { parts.push(format!("-u {}", k)); }
+        }
+
         // env assignments
         for (k, v) in &cmd.env {
-            parts.push(format!("{}={}", k, quote_sh(v)));
+            parts.push(format!("{}={}", k, quote(v)));
+        }
+
+        // `nice` wraps the program, but must precede `env` (assignments
+        // before it would be passed to nice, after it they'd be commands)
+        if let Some(n) = cmd.flags.nice {
+            let at = if via_env { 0 } else { parts.len() };
+            parts.insert(at, format!("nice -n {}", n));
         }
 
         // program
-        parts.push(quote_prog(&cmd.program));
+        parts.push(quote_prog(&cmd.program, quote));
 
-        // args
+        // args; `--flag value` pairs share a part so pretty plans keep them
+        // on one line
+        let mut flag = false;
         for a in &cmd.args {
-            parts.push(quote_sh(a));
+            let bytes = a.as_encoded_bytes();
+            match parts.last_mut() {
+                Some(prev) if flag && !bytes.starts_with(b"-") => { prev.push(' '); prev.push_str(&quote(a)); flag = false; }
+                _ => { parts.push(quote(a)); flag = is_flag(bytes); }
+            }
         }
 
         // redirections
         // stdin
-        if let Some(r) = render_redir(0, &cmd.stdin) { parts.push(r); }
+        let mut doc = None;
+        let mut feed = None;
+        match cmd.stdin.in_memory() {
+            Some(data) => match stdin_feed(data, self.bash, quote)? {
+                Feed::Redirect(redir, body) => { parts.push(redir); doc = body; }
+                Feed::Printf(p) => feed = Some(p),
+            },
+            None => if let Some(r) = render_redir(0, &cmd.stdin, quote)? { parts.push(r); },
+        }
         // stdout
-        if let Some(r) = render_redir(1, &cmd.stdout) { parts.push(r); }
+        if let Some(r) = render_redir(1, &cmd.stdout, quote)? { parts.push(r); }
         // stderr
-        if let Some(r) = render_redir(2, &cmd.stderr) { parts.push(r); }
+        if let Some(r) = render_redir(2, &cmd.stderr, quote)? { parts.push(r); }
+        if let Some(p) = feed { parts.insert(0, p); }
 
-        let mut cmd_str = parts.join(" ");
+        // limits, umask and cwd via `… &&`; ulimit/umask always get a
+        // subshell since they change the shell itself
+        let mut prelude: Vec<String> = Vec::new();
+        for (&limit, &v) in &cmd.flags.rlimits { prelude.push(format!("ulimit {} &&", ulimit_arg(limit, v))); }
+        if let Some(m) = cmd.flags.umask { prelude.push(format!("umask {:03o} &&", m)); }
+        let scoped = !prelude.is_empty();
+        if let Some(dir) = &cmd.cwd { prelude.push(format!("cd {} &&", quote(dir))); }
+        let subshell = !prelude.is_empty() && (isolate || scoped);
+        prelude.append(&mut parts);
+        if subshell {
+            prelude[0].insert(0, '(');
+            if let Some(last) = prelude.last_mut() { last.push(')'); }
+        }
 
-        // cwd via cd &&
-        if let Some(dir) = &cmd.cwd {
-            cmd_str = format!("cd {} && {}", quote_sh(dir), cmd_str);
+        let line = match (pretty, self.width) {
+            (Some(start), Some(width)) => fill(&prelude, width, start, " \\"),
+            _ => prelude.join(" "),
+        };
+        Ok((line, doc))
+    }
+}
+
+/// An option that may take the next argument as its value: `-v`, `--name`,
+/// but not `-` or `--name=value`.
+fn is_flag(arg: &[u8]) -> bool {
+    arg.len() > 1 && arg.starts_with(b"-") && arg != b"--" && !arg.contains(&b'=')
+}
+
+/// Break `parts` into lines of at most `width` columns for a pretty plan.
+/// The first line starts at column `start`; lines end in `cont` and the next
+/// is indented four columns further.
+fn fill(parts: &[String], width: usize, start: usize, cont: &str) -> String {
+    let indent = " ".repeat(start + 4);
+    let mut out = String::new();
+    let mut col = start;
+    for (i, part) in parts.iter().enumerate() {
+        let len = part.chars().count();
+        if i > 0 && col + 1 + len + cont.len() > width {
+            let _ = write!(out, "{}\n{}", cont, indent);
+            col = indent.len();
+        } else if i > 0 {
+            out.push(' ');
+            col += 1;
+        }
+        out.push_str(part);
+        col += len;
+    }
+    out
+}
+
+/// A pretty plan's note for one stage: `prog: timeout=…`.
+fn stage_note(cmd: &CommandSpec, process_attrs: bool) -> Option<String> {
+    let meta = plan_meta(cmd, process_attrs);
+    let meta = meta.strip_prefix("  # ")?;
+    Some(format!("{}: {}", cmd.program.to_string_lossy(), meta))
+}
+
+fn with_notes(line: String, notes: Vec<String>) -> String {
+    if notes.is_empty() { line } else { format!("{}  # {}", line, notes.join("; ")) }
+}
+
+/// Map accepted exit statuses to 0 so `&&`/`||` see success, and rejected
+/// ones to failure, 0 included (as 1):
+/// `{ LINE && sx_s=0 || sx_s=$?; case $sx_s in 1|2) ;; *) (exit $((sx_s ? sx_s : 1))) ;; esac; }`.
+/// The `&&`/`||` keep a rejected status from stopping a `set -e` script.
+fn judge_status(line: &str, success: &SuccessCriteria) -> Result<String, SyntaxError> {
+    if success.stdout_contains.is_some() || success.stdout_non_empty {
+        return Err(SyntaxError::RenderError("stdout success criteria cannot be rendered in a shell list".into()));
+    }
+    let codes: Vec<String> = success.statuses.iter().map(|c| c.to_string()).collect();
+    Ok(format!("{{ {} && sx_s=0 || sx_s=$?; case $sx_s in {}) ;; *) (exit $((sx_s ? sx_s : 1))) ;; esac; }}", line, codes.join("|")))
+}
+
+/// Wrap a line in a subshell loop that re-runs it per `policy`:
+/// `(for sx_d in 0.5 1 -; do LINE && exit 0; sx_s=$?; …; sleep "$sx_d"; done)`.
+/// Each word of the `for` list is the wait before the next attempt; `-` marks
+/// the last attempt. Jitter is not rendered.
+fn retry_loop(line: &str, policy: &RetryPolicy) -> Result<String, SyntaxError> {
+    if !policy.on_stderr.is_empty() {
+        return Err(SyntaxError::RenderError("stderr retry conditions cannot be rendered as a shell loop".into()));
+    }
+    let mut delays: Vec<String> = (1..=policy.retries()).map(|n| secs(policy.delay(n).as_millis() as u64)).collect();
+    delays.push("-".to_string());
+    let filter = if policy.on_status.is_empty() {
+        String::new()
+    } else {
+        let codes: Vec<String> = policy.on_status.iter().map(|c| c.to_string()).collect();
+        format!("case $sx_s in {}) ;; *) exit $sx_s ;; esac; ", codes.join("|"))
+    };
+    Ok(format!(
+        "(for sx_d in {}; do {} && exit 0; sx_s=$?; {}[ \"$sx_d\" = - ] && exit $sx_s; sleep \"$sx_d\"; done)",
+        delays.join(" "), line, filter
+    ))
+}
+
+/// Milliseconds as a `sleep` operand: `2`, `0.25`.
+fn secs(ms: u64) -> String {
+    if ms.is_multiple_of(1000) { return (ms / 1000).to_string(); }
+    format!("{}.{:03}", ms / 1000, ms % 1000).trim_end_matches('0').to_string()
+}
+
+/// `ulimit` option and value; sizes are given to the shell in KiB and
+/// `u64::MAX` means unlimited.
+fn ulimit_arg(limit: Rlimit, v: u64) -> String {
+    let (opt, unit) = match limit {
+        Rlimit::Cpu => ("-t", 1),
+        Rlimit::Memory => ("-v", 1024),
+        Rlimit::Data => ("-d", 1024),
+        Rlimit::Stack => ("-s", 1024),
+        Rlimit::OpenFiles => ("-n", 1),
+    };
+    if v == u64::MAX { format!("{} unlimited", opt) } else { format!("{} {}", opt, v / unit) }
+}
+
+/// Plan comment for flags the rendered line does not carry. Renderers that
+/// cannot express process attributes list them here too.
+fn plan_meta(cmd: &CommandSpec, process_attrs: bool) -> String {
+    let mut metas = Vec::new();
+    if let Some(ms) = cmd.flags.timeout_ms { metas.push(format!("timeout={}ms", ms)); }
+    if cmd.flags.retry.retries() > 0 { metas.push(format!("retries={}", cmd.flags.retry.retries())); }
+    if process_attrs {
+        if let Some(n) = cmd.flags.nice { metas.push(format!("nice={}", n)); }
+        if let Some(m) = cmd.flags.umask { metas.push(format!("umask={:03o}", m)); }
+        for (limit, v) in &cmd.flags.rlimits { metas.push(format!("rlimit.{}={}", limit.name(), v)); }
+    }
+    if cmd.success.is_custom() {
+        if !cmd.success.statuses.is_empty() {
+            let codes: Vec<String> = cmd.success.statuses.iter().map(|c| c.to_string()).collect();
+            metas.push(format!("ok_status={}", codes.join("|")));
+        }
+        if let Some(t) = &cmd.success.stdout_contains { metas.push(format!("stdout_contains={:?}", t)); }
+        if cmd.success.stdout_non_empty { metas.push("stdout_non_empty".to_string()); }
+    }
+    if metas.is_empty() { String::new() } else { format!("  # {}", metas.join(", ")) }
+}
+
+/// How in-memory stdin reaches a POSIX command: a redirection, with the
+/// here-doc body that has to follow the line, or a `printf … |` in front.
+enum Feed { Redirect(String, Option<String>), Printf(String) }
+
+/// Text ending in a newline becomes a quoted here-doc (a here-string in bash
+/// mode). Anything else is written by `printf`, since a here-doc always ends
+/// in a newline and can only carry text; the command then reads exactly the
+/// bytes the executor would feed it. Empty input is `< /dev/null`.
+fn stdin_feed(data: &[u8], bash: bool, quote: fn(&OsStr) -> String) -> Result<Feed, SyntaxError> {
+    if data.is_empty() { return Ok(Feed::Redirect("< /dev/null".to_string(), None)); }
+    if data.contains(&0) {
+        return Err(SyntaxError::RenderError("in-memory stdin contains a NUL byte".into()));
+    }
+    let text = std::str::from_utf8(data).ok();
+    if let Some(line) = data.strip_suffix(b"\n") {
+        if bash { return Ok(Feed::Redirect(format!("<<< {}", quote_bash_bytes(line)), None)); }
+        if let Some(text) = text {
+            let (redir, body) = heredoc(text);
+            return Ok(Feed::Redirect(redir, Some(body)));
         }
+    }
+    Ok(Feed::Printf(match text {
+        _ if bash => format!("printf '%s' {} |", quote_bash_bytes(data)),
+        Some(text) => format!("printf '%s' {} |", quote(OsStr::new(text))),
+        None => format!("printf {} |", quote_sh(printf_format(data))),
+    }))
+}
 
-        Ok(cmd_str)
+/// A quoted here-doc for newline-terminated text. Returns the redirection
+/// and the body, terminated by a delimiter that no line of it matches.
+fn heredoc(text: &str) -> (String, String) {
+    let mut delim = "SX_EOF".to_string();
+    let mut n = 0;
+    while text.split('\n').any(|l| l == delim) {
+        n += 1;
+        delim = format!("SX_EOF_{}", n);
     }
+    (format!("<<'{}'", delim), format!("{}{}", text, delim))
+}
 
-    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
-        let mut s = <Self as Renderer>::render_cmd(self, cmd)?;
-        let mut metas = Vec::new();
-        if let Some(ms) = cmd.flags.timeout_ms { metas.push(format!("timeout={}ms", ms)); }
-        if cmd.flags.retries > 0 { metas.push(format!("retries={}", cmd.flags.retries)); }
-        if !metas.is_empty() { s.push_str(&format!("  # {}", metas.join(", "))); }
-        Ok(s)
+/// A `printf` format writing `data` verbatim: `%` and `\` are escaped and
+/// bytes that are not valid UTF-8 are written as octal `\ooo`.
+fn printf_format(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.utf8_chunks() {
+        for ch in chunk.valid().chars() {
+            match ch {
+                '%' => out.push_str("%%"),
+                '\\' => out.push_str("\\\\"),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() { let _ = write!(out, "\\{:03o}", b); }
     }
-    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
-        let mut parts = Vec::new();
-        for c in &pipe.0 { parts.push(self.render_cmd_plan(c)?); }
-        let mut s = parts.join(" | ");
-        if let Some(last) = pipe.0.last() { if last.flags.background { s.push_str(" &"); } }
-        Ok(s)
+    out
+}
+
+fn attach_heredocs(mut line: String, docs: Vec<String>) -> String {
+    for d in docs {
+        line.push('\n');
+        line.push_str(&d);
     }
+    line
 }
 
+/// Renders for cmd.exe. Every value passes two parsers: cmd.exe's own
+/// (`%VAR%` expansion, then `^ & | < > ( )` outside quotes) and the
+/// program's runtime splitting the command line into argv (MSVCRT /
+/// `CommandLineToArgvW` rules). Arguments are quoted for the second and the
+/// result escaped for the first. Each side of a `|` is parsed again by a
+/// child cmd.exe, so pipeline stages, and commands fed echoed stdin, are
+/// escaped twice.
+///
+/// `batch` selects where the line will run: in a `.cmd`/`.bat` file `%` is
+/// written `%%`; on a command line (`cmd /c`, the default) it is
+/// caret-escaped instead. `cwd` and `env` are set between `setlocal` and
+/// `endlocal`, which only scope them inside batch files; on a command line
+/// they last for the rest of the line. Delayed expansion (`!VAR!`) is assumed
+/// to be off, as it is by default.
+///
+/// `width` lays plans out like [`PosixRenderer::width`], with ` ^`
+/// continuations.
 #[derive(Debug, Clone)]
-pub struct WinRenderer { pub quote: QuotePolicy }
+pub struct WinRenderer {
+    /// Ignored: cmd.exe lines are always quoted minimally.
+    #[deprecated(note = "has no effect; cmd.exe lines are always quoted minimally")]
+    pub quote: QuotePolicy,
+    pub batch: bool,
+    pub width: Option<usize>,
+}
 
-impl Default for WinRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+#[allow(deprecated)]
+impl Default for WinRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict, batch: false, width: None } } }
 
 impl Renderer for WinRenderer {
     fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        if cmd.flags.has_process_attrs() {
+            return Err(SyntaxError::RenderError("nice/umask/rlimits cannot be applied by cmd.exe".into()));
+        }
+        self.render_line(cmd, None, false)
+    }
+
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        let meta = plan_meta(cmd, true);
+        let notes = meta.strip_prefix("  # ").map(str::to_string).into_iter().collect();
+        Ok(with_rem(self.render_line(&cmd.redacted(), self.width.map(|_| 0), false)?, notes))
+    }
+
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        self.render_pipe_line(pipe, false, false, &mut Vec::new())
+    }
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let s = self.render_pipe_line(pipe, true, false, &mut notes)?;
+        Ok(with_rem(s, notes))
+    }
+
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let (s, _) = render_list(script, &WIN_LIST, &mut |p| Ok((self.render_pipe_line(p, false, true, &mut Vec::new())?, Vec::new())))?;
+        Ok(s)
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let syn = if self.width.is_some() { &WIN_PRETTY_LIST } else { &WIN_LIST };
+        let mut notes = Vec::new();
+        let (s, _) = render_list(script, syn, &mut |p| Ok((self.render_pipe_line(p, true, true, &mut notes)?, Vec::new())))?;
+        Ok(with_rem(s, notes))
+    }
+}
+
+impl WinRenderer {
+    /// `pretty` is the column a pretty plan line starts at. `piped` is set
+    /// for a stage of a pipeline, which a child cmd.exe parses again; so is
+    /// the command after an echoed stdin block.
+    fn render_line(&self, cmd: &CommandSpec, pretty: Option<usize>, piped: bool) -> Result<String, SyntaxError> {
         if cmd.program.is_empty() {
             return Err(SyntaxError::RenderError("program empty".into()));
         }
+        let batch = self.batch;
+        let depth = piped as u8;
 
-        // Compose: cd /d, env via set, program + args, redirections
+        // Compose: setlocal, cd /d, env via set, program + args, redirections
         let mut parts: Vec<String> = Vec::new();
+        let scoped = Self::scoped(cmd);
+        if scoped { parts.push("setlocal &&".to_string()); }
 
         // cwd
         if let Some(dir) = &cmd.cwd {
-            parts.push(format!("cd /d {} &&", quote_win(dir)));
+            parts.push(format!("cd /d {} &&", path_win(win_str(dir)?, batch, depth)?));
+        }
+
+        // env clearing/removal
+        if cmd.env_clear {
+            let v = if batch { "%%V" } else { "%V" };
+            let keep: String = cmd.env_keep.iter().map(|k| format!("if /i not \"{}\"==\"{}\" ", v, k)).collect();
+            parts.push(format!("(for /f \"delims==\" {v} in ('set') do @{}set \"{v}=\") &&", keep, v = v));
+        }
+        for k in &cmd.env_remove {
+            parts.push(format!("set \"{}=\" &&", k));
         }
 
-        // env (chain set commands)
+        // env: `set "K=V"` takes the value up to the last quote
         for (k, v) in &cmd.env {
-            parts.push(format!("set \"{}={}\" &&", k, v.replace('"', "\"")));
+            parts.push(format!("set {} &&", escape_cmd_at(&format!("\"{}={}\"", k, win_str(v)?), batch, depth)?));
+        }
+
+        // in-memory stdin is echoed into the command, through one more pipe
+        let depth = depth + cmd.stdin.in_memory().is_some() as u8;
+        if let Some(data) = cmd.stdin.in_memory() {
+            parts.push(echo_block_win(data, batch, depth)?);
         }
 
         // program + args
-        parts.push(quote_prog_win(&cmd.program));
-        for a in &cmd.args { parts.push(quote_win(a)); }
+        parts.push(path_win(win_str(&cmd.program)?, batch, depth)?);
+        let mut flag = false;
+        for a in &cmd.args {
+            let a = win_str(a)?;
+            let q = escape_cmd_at(&quote_argv_win(a), batch, depth)?;
+            match parts.last_mut() {
+                Some(prev) if flag && !a.starts_with('-') => { prev.push(' '); prev.push_str(&q); flag = false; }
+                _ => { parts.push(q); flag = is_flag(a.as_bytes()); }
+            }
+        }
 
         // redirections
-        if let Some(r) = render_redir_win(0, &cmd.stdin) { parts.push(r); }
-        if let Some(r) = render_redir_win(1, &cmd.stdout) { parts.push(r); }
-        if let Some(r) = render_redir_win(2, &cmd.stderr) { parts.push(r); }
+        if cmd.stdin.in_memory().is_none() {
+            if let Some(r) = render_redir_win(0, &cmd.stdin, batch, depth)? { parts.push(r); }
+        }
+        if let Some(r) = render_redir_win(1, &cmd.stdout, batch, depth)? { parts.push(r); }
+        if let Some(r) = render_redir_win(2, &cmd.stderr, batch, depth)? { parts.push(r); }
+        if scoped { parts.push("& endlocal".to_string()); }
 
-        Ok(parts.join(" "))
+        Ok(match (pretty, self.width) {
+            (Some(start), Some(width)) => fill(&parts, width, start, " ^"),
+            _ => parts.join(" "),
+        })
     }
-}
 
-impl WinRenderer {
-    pub fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+    fn scoped(cmd: &CommandSpec) -> bool {
+        cmd.cwd.is_some() || !cmd.env.is_empty() || cmd.env_clear || !cmd.env_remove.is_empty()
+    }
+
+    /// Inside pipelines and lists, `setlocal … & endlocal` stages are wrapped
+    /// in `( … )`. Plans collect each stage's plan notes in `notes`.
+    fn render_pipe_line(&self, pipe: &PipelineSpec, plan: bool, in_list: bool, notes: &mut Vec<String>) -> Result<String, SyntaxError> {
+        if pipe.opts.pipefail && pipe.stages.len() > 1 {
+            return Err(SyntaxError::RenderError("pipefail is not supported by cmd.exe".into()));
+        }
+        if !plan && in_list && pipe.stages.last().is_some_and(|c| c.success.is_custom()) {
+            return Err(SyntaxError::RenderError("custom success criteria cannot be rendered for cmd.exe lists".into()));
+        }
+        let piped = pipe.stages.len() > 1;
+        let isolate = in_list || piped;
+        let pretty = plan && self.width.is_some();
         let mut parts = Vec::new();
-        for c in &pipe.0 {
-            let s = <Self as Renderer>::render_cmd(self, c)?;
-            let mut s2 = s;
-            let mut metas = Vec::new();
-            if let Some(ms) = c.flags.timeout_ms { metas.push(format!("timeout={}ms", ms)); }
-            if c.flags.retries > 0 { metas.push(format!("retries={}", c.flags.retries)); }
-            if !metas.is_empty() { s2.push_str(&format!("  # {}", metas.join(", "))); }
-            parts.push(s2);
-        }
-        let mut s = parts.join(" | ");
-        if let Some(last) = pipe.0.last() { if last.flags.background { s.push_str(" &"); } }
+        for (i, c) in pipe.effective_stages()?.enumerate() {
+            if !plan && c.flags.has_process_attrs() {
+                return Err(SyntaxError::RenderError("nice/umask/rlimits cannot be applied by cmd.exe".into()));
+            }
+            let start = pretty.then_some(if i == 0 { 0 } else { 4 });
+            let mut s = if plan { self.render_line(&c.redacted(), start, piped)? } else { self.render_line(&c, None, piped)? };
+            if isolate && Self::scoped(&c) { s = format!("({})", s); }
+            if plan { notes.extend(stage_note(&c, true)); }
+            parts.push(s);
+        }
+        let mut s = parts.join(if pretty { " ^\n  | " } else { " | " });
+        if plan {
+            if let Some(last) = pipe.stages.last() { if last.flags.background { s.push_str(" &"); } }
+        }
         Ok(s)
     }
 }
 
-impl PosixRenderer { }
+/// cmd.exe has no `#` comments: plan notes go in a trailing `rem`, which
+/// ignores the rest of the line. A background plan's `&` already separates it.
+fn with_rem(line: String, notes: Vec<String>) -> String {
+    match (notes.is_empty(), line.ends_with(" &")) {
+        (true, _) => line,
+        (false, true) => format!("{} rem {}", line, notes.join("; ")),
+        (false, false) => format!("{} & rem {}", line, notes.join("; ")),
+    }
+}
 
-fn render_redir(fd: u8, io: &Stdio) -> Option<String> {
-    match (fd, io) {
+/// POSIX-style redirections (also used by fish), with paths quoted by `quote`.
+fn render_redir(fd: u8, io: &Stdio, quote: fn(&OsStr) -> String) -> Result<Option<String>, SyntaxError> {
+    Ok(match (fd, io) {
         (0, Stdio::Inherit) | (1, Stdio::Inherit) | (2, Stdio::Inherit) => None,
         (0, Stdio::Null) => Some("< /dev/null".to_string()),
         (1, Stdio::Null) => Some("> /dev/null".to_string()),
         (2, Stdio::Null) => Some("2> /dev/null".to_string()),
-        (0, Stdio::File { path, .. }) => Some(format!("< {}", quote_sh(path))),
-        (1, Stdio::File { path, append }) => Some(format!("{} {}", if *append { ">>" } else { ">" }, quote_sh(path))),
-        (2, Stdio::File { path, append }) => Some(format!("2{} {}", if *append { ">>" } else { ">" }, quote_sh(path))),
+        (0, Stdio::File { path, .. }) => Some(format!("< {}", quote(path))),
+        (1, Stdio::File { path, append }) => Some(format!("{} {}", if *append { ">>" } else { ">" }, quote(path))),
+        (2, Stdio::File { path, append }) => Some(format!("2{} {}", if *append { ">>" } else { ">" }, quote(path))),
+        (1, Stdio::Dup(_)) => { check_dup(fd, io)?; Some(">&2".to_string()) }
+        (2, Stdio::Dup(_)) => { check_dup(fd, io)?; Some("2>&1".to_string()) }
+        (_, Stdio::Dup(_)) => { check_dup(fd, io)?; None }
+        (_, Stdio::Bytes(_)) | (_, Stdio::Text(_)) => return Err(in_memory_output(fd)),
         (_, Stdio::Pipe) => None,
         _ => None,
-    }
+    })
 }
 
-fn render_redir_win(fd: u8, io: &Stdio) -> Option<String> {
-    match (fd, io) {
+fn render_redir_win(fd: u8, io: &Stdio, batch: bool, depth: u8) -> Result<Option<String>, SyntaxError> {
+    Ok(match (fd, io) {
         (0, Stdio::Inherit) | (1, Stdio::Inherit) | (2, Stdio::Inherit) => None,
         (0, Stdio::Null) => Some("< NUL".to_string()),
         (1, Stdio::Null) => Some("> NUL".to_string()),
         (2, Stdio::Null) => Some("2> NUL".to_string()),
-        (0, Stdio::File { path, .. }) => Some(format!("< {}", quote_win(path))),
-        (1, Stdio::File { path, append }) => Some(format!("{} {}", if *append { ">>" } else { ">" }, quote_win(path))),
-        (2, Stdio::File { path, append }) => Some(format!("2{} {}", if *append { ">>" } else { ">" }, quote_win(path))),
+        (0, Stdio::File { path, .. }) => Some(format!("< {}", path_win(win_str(path)?, batch, depth)?)),
+        (1, Stdio::File { path, append }) => Some(format!("{} {}", if *append { ">>" } else { ">" }, path_win(win_str(path)?, batch, depth)?)),
+        (2, Stdio::File { path, append }) => Some(format!("2{} {}", if *append { ">>" } else { ">" }, path_win(win_str(path)?, batch, depth)?)),
+        (1, Stdio::Dup(_)) => { check_dup(fd, io)?; Some("1>&2".to_string()) }
+        (2, Stdio::Dup(_)) => { check_dup(fd, io)?; Some("2>&1".to_string()) }
+        (_, Stdio::Dup(_)) => { check_dup(fd, io)?; None }
+        (_, Stdio::Bytes(_)) | (_, Stdio::Text(_)) => return Err(in_memory_output(fd)),
         (_, Stdio::Pipe) => None,
         _ => None,
+    })
+}
+
+fn in_memory_output(fd: u8) -> SyntaxError {
+    SyntaxError::RenderError(format!("in-memory data is only valid for stdin, not fd {}", fd))
+}
+
+/// Feed in-memory stdin on cmd.exe as `(echo(line1&echo(line2) |`, or
+/// `< NUL` when empty. The block is one side of a pipe, so its lines are
+/// escaped for `depth` parses with [`escape_cmd_at`].
+fn echo_block_win(data: &[u8], batch: bool, depth: u8) -> Result<String, SyntaxError> {
+    if data.is_empty() { return Ok("< NUL".to_string()); }
+    let text = std::str::from_utf8(data)
+        .map_err(|_| SyntaxError::RenderError("echoed stdin is not valid UTF-8".into()))?;
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        lines.push(format!("echo({}", escape_cmd_at(line, batch, depth)?));
+    }
+    Ok(format!("({}) |", lines.join("&")))
+}
+
+/// Only stdout/stderr may be duplicated, and only onto each other.
+fn check_dup(fd: u8, io: &Stdio) -> Result<(), SyntaxError> {
+    match (fd, io) {
+        (1, Stdio::Dup(2)) | (2, Stdio::Dup(1)) => Ok(()),
+        (_, Stdio::Dup(to)) => Err(SyntaxError::RenderError(format!("unsupported fd duplication {}>&{}", fd, to))),
+        _ => Ok(()),
     }
 }
 
-fn quote_prog(p: &str) -> String {
-    // Allow bare if simple, else quote
-    if is_simple_word(p) { p.to_string() } else { quote_sh(p) }
+fn quote_prog(p: &OsStr, quote: fn(&OsStr) -> String) -> String {
+    // Allow bare if simple, else quote (a bare `a=b` would be an assignment)
+    match p.to_str() {
+        Some(s) if is_simple_word(s) && !s.contains('=') => s.to_string(),
+        _ => quote(p),
+    }
 }
 
 fn is_simple_word(s: &str) -> bool {
@@ -178,10 +783,64 @@ fn is_simple_word(s: &str) -> bool {
         'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' | '/' | ':' | '+' | '%' | '@' | '=' | ','))
 }
 
-fn quote_sh(s: &str) -> String {
-    if s.is_empty() { return "''".to_string(); }
-    let escaped = s.replace("'", "'\"'\"'");
-    format!("'{}'", escaped)
+/// Single-quote a value byte-exactly. Bytes that are not valid UTF-8 are
+/// emitted as adjacent ANSI-C `$'\xNN'` words.
+pub(crate) fn quote_sh(s: impl AsRef<OsStr>) -> String {
+    let bytes = s.as_ref().as_encoded_bytes();
+    if bytes.is_empty() { return "''".to_string(); }
+    let mut out = String::new();
+    let mut in_ansi = false;
+    for chunk in bytes.utf8_chunks() {
+        if !chunk.valid().is_empty() {
+            if in_ansi { out.push('\''); in_ansi = false; }
+            let escaped = chunk.valid().replace("'", "'\"'\"'");
+            out.push_str(&format!("'{}'", escaped));
+        }
+        if !chunk.invalid().is_empty() {
+            if !in_ansi { out.push_str("$'"); in_ansi = true; }
+            for b in chunk.invalid() { let _ = write!(out, "\\x{:02x}", b); }
+        }
+    }
+    if in_ansi { out.push('\''); }
+    out
+}
+
+/// A value that needs no quotes in any POSIX shell, bash or zsh: no globs,
+/// `~`, braces, `$`, `!` or blanks, and no leading `=` (zsh's `=cmd`).
+fn bare_word(s: &OsStr) -> Option<String> {
+    let s = s.to_str()?;
+    (!s.is_empty() && !s.starts_with('=') && is_simple_word(s)).then(|| s.to_string())
+}
+
+fn quote_bash(s: &OsStr) -> String { quote_bash_bytes(s.as_encoded_bytes()) }
+
+/// Quote for bash: `'…'` when every character is printable, otherwise one
+/// ANSI-C `$'…'` string with `\n`, `\t`, `\r`, `\e` and `\xNN` escapes.
+fn quote_bash_bytes(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) if !s.chars().any(char::is_control) => return quote_sh(s),
+        _ => {}
+    }
+    let mut out = String::from("$'");
+    for chunk in bytes.utf8_chunks() {
+        for ch in chunk.valid().chars() {
+            match ch {
+                '\\' => out.push_str("\\\\"),
+                '\'' => out.push_str("\\'"),
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                '\r' => out.push_str("\\r"),
+                '\x1b' => out.push_str("\\e"),
+                c if c.is_control() => {
+                    for b in c.encode_utf8(&mut [0; 4]).bytes() { let _ = write!(out, "\\x{:02x}", b); }
+                }
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() { let _ = write!(out, "\\x{:02x}", b); }
+    }
+    out.push('\'');
+    out
 }
 
 fn is_simple_word_win(s: &str) -> bool {
@@ -190,20 +849,88 @@ fn is_simple_word_win(s: &str) -> bool {
         'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' | '/' | '\\' | ':' | '+' | '%' | '@' | '=' | ','))
 }
 
-fn quote_win(s: &str) -> String {
-    if is_simple_word_win(s) { return s.to_string(); }
-    let escaped = s.replace('"', "\"\"");
-    format!("\"{}\"", escaped)
+/// A program or file path for cmd.exe: bare when simple, else in double
+/// quotes. cmd.exe needs real quotes to keep such a path one token, and
+/// carets are literal inside them, so on the command line (and so in any
+/// pipeline stage) a quoted path cannot contain `%`.
+fn path_win(p: &str, batch: bool, depth: u8) -> Result<String, SyntaxError> {
+    if p.contains('"') { return Err(SyntaxError::RenderError("path contains a double quote".into())); }
+    if is_simple_word_win(p) { return escape_cmd_at(p, batch, depth); }
+    if (!batch || depth > 0) && p.contains('%') {
+        return Err(SyntaxError::RenderError("'%' in a quoted path cannot be escaped on the cmd.exe command line".into()));
+    }
+    escape_cmd_at(&format!("\"{}\"", p), batch, depth)
+}
+
+/// Escape text for cmd.exe's parser, which runs before the program sees its
+/// command line.
+///
+/// A value in one pair of quotes keeps them when nothing inside needs
+/// escaping there (cmd.exe leaves quoted text alone except for `%`).
+/// Otherwise every `"` is written `^"` so cmd.exe never enters quote mode,
+/// and `^ & | < > ( )` are caret-escaped. `%` becomes `%%` in batch files; on
+/// the command line it becomes `^%` and the next character is escaped too,
+/// so `%NAME%` (or `%NAME:a=b%`) never names a defined variable. Line breaks
+/// cannot be passed at all.
+fn escape_cmd(s: &str, batch: bool) -> Result<String, SyntaxError> {
+    if s.contains(['\n', '\r']) {
+        return Err(SyntaxError::RenderError("line breaks cannot be passed through cmd.exe".into()));
+    }
+    let inner = s.strip_prefix('"').and_then(|t| t.strip_suffix('"'));
+    if let Some(inner) = inner.filter(|t| !t.contains('"') && (batch || !t.contains('%'))) {
+        return Ok(format!("\"{}\"", inner.replace('%', "%%")));
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut after_pct = false;
+    for ch in s.chars() {
+        if ch == '%' && batch {
+            out.push_str("%%");
+            continue;
+        }
+        if after_pct || matches!(ch, '%' | '"' | '^' | '&' | '|' | '<' | '>' | '(' | ')') { out.push('^'); }
+        out.push(ch);
+        after_pct = ch == '%';
+    }
+    Ok(out)
+}
+
+/// Escape text that cmd.exe parses `depth` more times after the first
+/// pass: each side of a `|` runs in a child `cmd /c` that parses its text
+/// again, in command-line mode whether or not the parent is a batch file.
+fn escape_cmd_at(s: &str, batch: bool, depth: u8) -> Result<String, SyntaxError> {
+    let mut s = s.to_string();
+    for _ in 0..depth { s = escape_cmd(&s, false)?; }
+    escape_cmd(&s, batch)
+}
+
+/// cmd.exe output is text; values must be valid Unicode.
+fn win_str(s: &OsStr) -> Result<&str, SyntaxError> {
+    s.to_str().ok_or_else(|| SyntaxError::RenderError("non-Unicode value cannot be rendered for cmd.exe".into()))
 }
 
-fn quote_prog_win(p: &str) -> String {
-    if is_simple_word_win(p) { p.to_string() } else { quote_win(p) }
+/// Quote one argument of a Windows command line as the MSVC runtime splits
+/// it: in double quotes, with backslashes doubled only where they precede a
+/// `"` (escaped as `\"`) or the closing quote.
+fn quote_argv_win(s: &str) -> String {
+    if !s.is_empty() && !s.contains([' ', '\t', '\n', '\x0b', '"']) { return s.to_string(); }
+    let mut out = String::from("\"");
+    let mut slashes = 0;
+    for ch in s.chars() {
+        if ch == '\\' { slashes += 1; continue; }
+        let n = if ch == '"' { 2 * slashes + 1 } else { slashes };
+        out.extend(std::iter::repeat_n('\\', n));
+        slashes = 0;
+        out.push(ch);
+    }
+    out.extend(std::iter::repeat_n('\\', 2 * slashes));
+    out.push('"');
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cmd::{CommandSpec, Stdio, PipelineSpec};
+    use crate::cmd::{CommandSpec, Stdio, PipelineSpec, ScriptSpec};
     use std::collections::BTreeMap;
 
     #[test]
@@ -214,19 +941,139 @@ mod tests {
         assert_eq!(quote_sh("foo'bar"), "'foo'\"'\"'bar'");
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn quote_non_utf8_bytes() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+        let name = OsString::from_vec(b"caf\xe9 it's\xff\xfe.txt".to_vec());
+        assert_eq!(quote_sh(&name), r#"'caf'$'\xe9'' it'"'"'s'$'\xff\xfe''.txt'"#);
+        let cmd = CommandSpec::new("cat").arg(name);
+        assert!(matches!(WinRenderer::default().render_cmd(&cmd), Err(SyntaxError::RenderError(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bash_render_ansi_c_quoting() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        let r = PosixRenderer { bash: true, ..Default::default() };
+        let odd = std::ffi::OsString::from_vec(b"caf\xe9\tit's\\\x1b[0m\r\n\x7f\xc2\x85".to_vec());
+        let cmd = CommandSpec::new("printf").args(["%s\\0", "plain it's", "é"]).arg(odd.clone())
+            .env("MSG", "a\nb").stdin_text("x\ny\n").stdout_to("out\nfile");
+        let line = r.render_cmd(&cmd).unwrap();
+        assert_eq!(line, concat!(
+            r#"MSG=$'a\nb' printf '%s\0' 'plain it'"'"'s' 'é' $'caf\xe9\tit\'s\\\e[0m\r\n\x7f\xc2\x85'"#,
+            r" <<< $'x\ny' > $'out\nfile'"));
+        assert!(!line.contains('\n'));
+        assert_eq!(r.render_cmd(&CommandSpec::new("cat").stdin_text("\n")).unwrap(), "cat <<< ''");
+        let no_stdin = cmd.clone().stdin(Stdio::Inherit);
+        assert_eq!(CommandSpec::parse(&r.render_cmd(&no_stdin).unwrap()).unwrap(), no_stdin);
+
+        // bash reads back exactly the original bytes
+        let probe = CommandSpec::new("printf").args(["%s\\0".into(), odd.clone(), "a\tb".into()]).env("MSG", "a\nb");
+        let script = format!("{} && printf '%s\\0' \"$MSG\"", r.render_cmd(&probe).unwrap());
+        let out = std::process::Command::new("bash").args(["-c", &script]).output().unwrap();
+        let want = [odd.as_bytes(), b"\0a\tb\0", b"\0"].concat();
+        assert_eq!(out.stdout, want);
+    }
+
+    #[test]
+    fn loose_quotes_only_when_needed() {
+        let r = PosixRenderer { quote: QuotePolicy::Loose, ..Default::default() };
+        let cmd = CommandSpec::new("git").args(["log", "--format=%h", "a/b.c:1", "x@y,z", "-", "", "a b", "*.rs", "~/x", "=ls", "{a,b}", "$HOME", "it's", "[x]"])
+            .env("A", "1").env("B", "two words").cwd("/src dir").stdout_to("out.log");
+        assert_eq!(r.render_cmd(&cmd).unwrap(), concat!(
+            "cd '/src dir' && A=1 B='two words' git log --format=%h a/b.c:1 x@y,z - '' 'a b' '*.rs' '~/x' '=ls' '{a,b}' '$HOME' 'it'\"'\"'s' '[x]'",
+            " > out.log"));
+        let bash = PosixRenderer { quote: QuotePolicy::Loose, bash: true, ..Default::default() };
+        assert_eq!(bash.render_cmd(&CommandSpec::new("echo").args(["a", "b\tc"])).unwrap(), "echo a $'b\\tc'");
+    }
+
+    /// Every ASCII character alone and every pair of shell-significant ones:
+    /// strict and loose lines must give `sh` (and `bash`) the same argv and env.
+    #[cfg(unix)]
+    #[test]
+    fn strict_and_loose_agree_under_sh() {
+        let mut values: Vec<String> = (1u8..0x80).map(|b| (b as char).to_string()).collect();
+        let special = "a-=~*?[]{},:%@+/.#!$'\"\\ \t\n;&|<>()^`";
+        for x in special.chars() { for y in special.chars() { values.push(format!("{}{}", x, y)); } }
+        values.extend(["", "--x=~/y", "a=b", "x~", "%1", "-n", "@", "a,b", "1:2", "é"].map(String::from));
+
+        // every value as an argument and as an env value, read back by one `sh -c`
+        let names: Vec<String> = (0..values.len()).map(|i| format!("V{}", i)).collect();
+        let body = format!(r#"printf '%s\0' "$@" {}"#, names.iter().map(|n| format!("\"${}\"", n)).collect::<Vec<_>>().join(" "));
+        let mut probe = CommandSpec::new("sh").args(["-c", body.as_str(), "sx"]).args(&values);
+        for (n, v) in names.iter().zip(&values) { probe = probe.env(n, v); }
+        let want: Vec<u8> = values.iter().chain(&values).flat_map(|v| [v.as_bytes(), b"\0"].concat()).collect();
+        for (shell, bash) in [("sh", false), ("bash", false), ("bash", true)] {
+            for quote in [QuotePolicy::Strict, QuotePolicy::Loose] {
+                let line = PosixRenderer { quote, bash, ..Default::default() }.render_cmd(&probe).unwrap();
+                let out = std::process::Command::new(shell).args(["-c", &line]).output().unwrap();
+                assert_eq!(out.stdout, want, "{} {:?} bash={}", shell, quote, bash);
+            }
+        }
+    }
+
+    #[test]
+    fn pretty_plans_wrap_at_width() {
+        use std::time::Duration;
+        let run = CommandSpec::new("docker")
+            .args(["run", "--rm", "-v", "/src:/src", "--name", "build box", "-e", "HOME=/root", "rust:1", "cargo", "build"])
+            .cwd("/src").timeout(Duration::from_secs(600))
+            .pipe(CommandSpec::new("tee").args(["-a", "build.log"]).retries(2));
+        let r = PosixRenderer { quote: QuotePolicy::Loose, width: Some(40), ..Default::default() };
+        assert_eq!(r.render_pipe_plan(&run).unwrap(), [
+            "(cd /src && docker run --rm \\",
+            "    -v /src:/src --name 'build box' \\",
+            "    -e HOME=/root rust:1 cargo build) \\",
+            "  | tee -a build.log  # docker: timeout=600000ms; tee: retries=2",
+        ].join("\n"));
+        let script = ScriptSpec::from(CommandSpec::new("make").stdin_text("x\n")).and_then(CommandSpec::new("echo").arg("done"));
+        assert_eq!(r.render_script_plan(&script).unwrap(), "make <<'SX_EOF' \\\n  && echo done\nx\nSX_EOF");
+        // the layout only changes plans
+        assert_eq!(r.render_pipe(&run).unwrap(), PosixRenderer { width: None, ..r.clone() }.render_pipe(&run).unwrap());
+
+        let w = WinRenderer { width: Some(40), ..Default::default() };
+        assert_eq!(w.render_pipe_plan(&run).unwrap(), [
+            "(setlocal && cd /d /src && docker run ^",
+            "    --rm -v /src:/src ^",
+            "    --name \"build box\" -e HOME=/root ^",
+            "    rust:1 cargo build & endlocal) ^",
+            "  | tee -a build.log & rem docker: timeout=600000ms; tee: retries=2",
+        ].join("\n"));
+    }
+
+    /// A pretty plan runs exactly like the one-line command.
+    #[cfg(unix)]
+    #[test]
+    fn pretty_plans_run_as_pasted() {
+        let words = ["--out", "a b", "-x", "--", "it's", "$HOME", "x\ny", "-v", "-"];
+        let cmd = CommandSpec::new("printf").arg("%s\\0").args(words).env("K", "v w").stdin_text("in\n")
+            .pipe(CommandSpec::new("od").args(["-A", "n", "-c"]));
+        for quote in [QuotePolicy::Strict, QuotePolicy::Loose] {
+            let flat = PosixRenderer { quote, ..Default::default() };
+            let pretty = PosixRenderer { quote, width: Some(20), ..Default::default() };
+            let script = ScriptSpec::from(cmd.clone()).then(CommandSpec::new("echo").arg("end"));
+            let run = |line: String| std::process::Command::new("sh").args(["-c", &line]).output().unwrap().stdout;
+            assert!(pretty.render_script_plan(&script).unwrap().lines().count() > 5);
+            assert_eq!(run(pretty.render_script_plan(&script).unwrap()), run(flat.render_script(&script).unwrap()));
+        }
+    }
+
     #[test]
     fn render_cmd_env_cwd_args() {
         let mut env = BTreeMap::new();
-        env.insert("FOO".to_string(), "bar baz".to_string());
+        env.insert("FOO".to_string(), "bar baz".into());
         let cmd = CommandSpec {
-            program: "echo".to_string(),
-            args: vec!["hi".to_string()],
+            program: "echo".into(),
+            args: vec!["hi".into()],
             env,
-            cwd: Some("/tmp".to_string()),
+            cwd: Some("/tmp".into()),
             stdin: Stdio::Inherit,
             stdout: Stdio::Inherit,
             stderr: Stdio::Inherit,
             flags: Default::default(),
+            ..Default::default()
         };
         let r = PosixRenderer::default();
         let got = r.render_cmd(&cmd).unwrap();
@@ -236,27 +1083,281 @@ mod tests {
     #[test]
     fn render_cmd_redirections() {
         let cmd = CommandSpec {
-            program: "/bin/cat".to_string(),
-            args: vec!["file.txt".to_string()],
+            program: "/bin/cat".into(),
+            args: vec!["file.txt".into()],
             env: BTreeMap::new(),
             cwd: None,
             stdin: Stdio::Null,
             stdout: Stdio::File { path: "out.txt".into(), append: true },
             stderr: Stdio::File { path: "err.txt".into(), append: false },
             flags: Default::default(),
+            ..Default::default()
         };
         let r = PosixRenderer::default();
         let got = r.render_cmd(&cmd).unwrap();
         assert_eq!(got, "/bin/cat 'file.txt' < /dev/null >> 'out.txt' 2> 'err.txt'");
     }
 
+    #[test]
+    fn render_cmd_fd_dups() {
+        let merged = CommandSpec {
+            program: "make".into(),
+            stdout: Stdio::File { path: "build.log".into(), append: false },
+            stderr: Stdio::Dup(1),
+            ..Default::default()
+        };
+        assert_eq!(PosixRenderer::default().render_cmd(&merged).unwrap(), "make > 'build.log' 2>&1");
+        assert_eq!(WinRenderer::default().render_cmd(&merged).unwrap(), "make > build.log 2>&1");
+
+        let to_err = CommandSpec { program: "echo".into(), args: vec!["oops".into()], stdout: Stdio::Dup(2), ..Default::default() };
+        assert_eq!(PosixRenderer::default().render_cmd(&to_err).unwrap(), "echo 'oops' >&2");
+        assert_eq!(WinRenderer::default().render_cmd(&to_err).unwrap(), "echo oops 1>&2");
+
+        let bad = CommandSpec { program: "cat".into(), stdin: Stdio::Dup(1), ..Default::default() };
+        assert!(matches!(PosixRenderer::default().render_cmd(&bad), Err(SyntaxError::RenderError(_))));
+        let self_dup = CommandSpec { program: "cat".into(), stderr: Stdio::Dup(2), ..Default::default() };
+        assert!(WinRenderer::default().render_cmd(&self_dup).is_err());
+    }
+
+    #[test]
+    fn render_in_memory_stdin() {
+        let mut p = PipelineSpec::new();
+        p.push(CommandSpec { program: "cat".into(), stdin: Stdio::Text("a $x\nSX_EOF\n".into()), ..Default::default() });
+        p.push(CommandSpec { program: "sort".into(), ..Default::default() });
+        let got = PosixRenderer::default().render_pipe(&p).unwrap();
+        assert_eq!(got, "cat <<'SX_EOF_1' | sort\na $x\nSX_EOF\nSX_EOF_1");
+
+        let cmd = CommandSpec { program: "findstr".into(), args: vec!["x".into()], stdin: Stdio::Bytes(b"a&b\r\n\n100%".to_vec()), ..Default::default() };
+        let got = WinRenderer::default().render_cmd(&cmd).unwrap();
+        // both sides of the `|` are parsed again by a child cmd.exe
+        assert_eq!(got, "(echo(a^^^&b&echo(&echo(100^^^%) | findstr x");
+        #[allow(deprecated)]
+        let loose = WinRenderer { quote: QuotePolicy::Loose, ..Default::default() };
+        assert_eq!(loose.render_cmd(&cmd).unwrap(), got);
+
+        let bad = CommandSpec { program: "cat".into(), stdout: Stdio::Text("x".into()), ..Default::default() };
+        assert!(PosixRenderer::default().render_cmd(&bad).is_err());
+    }
+
+    /// Input without a trailing newline, or that is not UTF-8, goes through
+    /// `printf` and reaches the command byte for byte.
+    #[cfg(unix)]
+    #[test]
+    fn in_memory_stdin_is_byte_exact() {
+        let cmd = CommandSpec::new("cat").stdin_text("abc");
+        assert_eq!(PosixRenderer::default().render_cmd(&cmd).unwrap(), "printf '%s' 'abc' | cat");
+        let raw = CommandSpec { stdin: Stdio::Bytes(b"\xff 100%\\n\n".to_vec()), ..CommandSpec::new("od") };
+        assert_eq!(PosixRenderer::default().render_cmd(&raw).unwrap(), "printf '\\377 100%%\\\\n\n' | od");
+        for data in [&b"abc"[..], b"a\nb", b"\xff 100%\\n\n", b"it's\n", b"\xfe\xff"] {
+            let cmd = CommandSpec { stdin: Stdio::Bytes(data.to_vec()), ..CommandSpec::new("cat") };
+            for (shell, bash) in [("sh", false), ("bash", true)] {
+                let line = PosixRenderer { bash, ..Default::default() }.render_cmd(&cmd).unwrap();
+                let Ok(out) = std::process::Command::new(shell).arg("-c").arg(&line).output() else { continue };
+                assert_eq!(out.stdout, data, "{} -c {:?}", shell, line);
+            }
+        }
+    }
+
+    /// Lists judged by `sh` agree with `StdExecutor` on accepted statuses.
+    #[cfg(unix)]
+    #[test]
+    fn success_statuses_decide_lists_under_sh() {
+        let sh = |s: &str| CommandSpec::new("sh").args(["-c", s]);
+        for (status, accepted, ran) in [(0, vec![1], false), (1, vec![1], true), (0, vec![0, 1], true), (2, vec![0, 1], false)] {
+            let script = ScriptSpec::from(sh(&format!("exit {}", status)).success_statuses(accepted.clone()))
+                .and_then(CommandSpec::new("echo").arg("ran"));
+            let line = PosixRenderer::default().render_script(&script).unwrap();
+            let out = std::process::Command::new("sh").args(["-c", &line]).output().unwrap();
+            assert_eq!(out.stdout == b"ran\n", ran, "exit {} accepting {:?}: {}", status, accepted, line);
+            assert_eq!(out.status.success(), ran, "{}", line);
+        }
+    }
+
+    #[test]
+    fn render_script_lists() {
+        let c = |p: &str| CommandSpec { program: p.into(), ..Default::default() };
+        let script = ScriptSpec::from(c("build"))
+            .and_then(c("test"))
+            .or_else(ScriptSpec::from(c("notify")).then(c("cleanup")))
+            .then(ScriptSpec::from(c("a")).and_then(c("b")).subshell());
+        let got = PosixRenderer::default().render_script(&script).unwrap();
+        assert_eq!(got, "build && test || { notify; cleanup; }; ( a && b )");
+        let got = WinRenderer::default().render_script(&script).unwrap();
+        assert_eq!(got, "((build && test) || (notify & cleanup)) & (a && b)");
+
+        let mut feed = c("cat");
+        feed.stdin = Stdio::Text("x\n".into());
+        let script = ScriptSpec::from(feed).and_then(ScriptSpec::from(c("a")).or_else(c("b")));
+        let got = PosixRenderer::default().render_script(&script).unwrap();
+        assert_eq!(got, "cat <<'SX_EOF' && { a || b; }\nx\nSX_EOF");
+
+        let bg = ScriptSpec::from(c("a").background());
+        assert_eq!(PosixRenderer::default().render_script_plan(&bg).unwrap(), "a &");
+        assert!(PosixRenderer::default().render_script_plan(&bg.clone().and_then(c("b"))).is_err());
+        assert!(WinRenderer::default().render_script_plan(&bg.group()).is_err());
+    }
+
+    #[test]
+    fn render_pipe_options() {
+        let p = CommandSpec::new("make").pipe(CommandSpec::new("tee").arg("log")).pipe(CommandSpec::new("wc"));
+        let both = p.clone().pipefail().merge_stderr();
+        assert_eq!(PosixRenderer::default().render_pipe(&both).unwrap(), "(set -o pipefail && make 2>&1 | tee 'log' 2>&1 | wc)");
+        assert_eq!(WinRenderer::default().render_pipe(&p.clone().merge_stderr()).unwrap(), "make 2>&1 | tee log 2>&1 | wc");
+        assert!(WinRenderer::default().render_pipe(&both).is_err());
+        // the merge would silently replace `2> err.log`
+        let logged = CommandSpec::new("make").stderr_to("err.log").pipe(CommandSpec::new("wc")).merge_stderr();
+        assert!(PosixRenderer::default().render_pipe(&logged).is_err());
+        assert!(logged.build().is_err());
+        // pipefail is meaningless for a single command
+        assert_eq!(PosixRenderer::default().render_pipe(&PipelineSpec::from(CommandSpec::new("true")).pipefail()).unwrap(), "true");
+    }
+
+    #[test]
+    fn render_process_attrs() {
+        use crate::cmd::Rlimit;
+        let cmd = CommandSpec::new("make").cwd("/src").env("CC", "cc").nice(10).umask(0o027)
+            .rlimit(Rlimit::Cpu, 60).rlimit(Rlimit::Memory, 512 << 20).rlimit(Rlimit::Stack, u64::MAX);
+        assert_eq!(
+            PosixRenderer::default().render_cmd(&cmd).unwrap(),
+            "(ulimit -t 60 && ulimit -v 524288 && ulimit -s unlimited && umask 027 && cd '/src' && CC='cc' nice -n 10 make)"
+        );
+        let cmd = CommandSpec::new("make").env_remove("X").nice(5);
+        assert_eq!(PosixRenderer::default().render_cmd(&cmd).unwrap(), "nice -n 5 env -u X make");
+
+        let cmd = CommandSpec::new("make").nice(10).rlimit(Rlimit::OpenFiles, 64);
+        assert!(WinRenderer::default().render_cmd(&cmd).is_err());
+        assert_eq!(WinRenderer::default().render_cmd_plan(&cmd).unwrap(), "make & rem nice=10, rlimit.open_files=64");
+    }
+
+    #[test]
+    fn render_retry_loops() {
+        use crate::cmd::{Backoff, RetryPolicy};
+        let r = PosixRenderer { retry_loops: true, ..Default::default() };
+        let policy = RetryPolicy::attempts(3).backoff(Backoff::Exponential { initial_ms: 500, factor: 2, max_ms: 5000 }).on_status(75);
+        let p = CommandSpec::new("curl").arg("-f").pipe(CommandSpec::new("sh").retry(policy));
+        assert_eq!(
+            r.render_pipe(&p).unwrap(),
+            "(for sx_d in 0.5 1 -; do curl '-f' | sh && exit 0; sx_s=$?; case $sx_s in 75) ;; *) exit $sx_s ;; esac; [ \"$sx_d\" = - ] && exit $sx_s; sleep \"$sx_d\"; done)"
+        );
+        assert!(r.render_cmd_plan(&CommandSpec::new("curl").retries(2)).unwrap().ends_with("# retries=2"));
+        assert!(r.render_pipe(&CommandSpec::new("x").retry(RetryPolicy::attempts(2).on_stderr("busy")).into()).is_err());
+        assert_eq!(
+            r.render_cmd(&CommandSpec::new("curl").retries(1)).unwrap(),
+            "(for sx_d in 0 -; do curl && exit 0; sx_s=$?; [ \"$sx_d\" = - ] && exit $sx_s; sleep \"$sx_d\"; done)"
+        );
+        assert_eq!(CommandSpec::new("x").retries(u32::MAX).flags.retry.max_attempts, u32::MAX);
+    }
+
+    #[test]
+    fn render_success_criteria_in_lists() {
+        let diff = CommandSpec::new("diff").args(["a", "b"]).success_statuses([0, 1]);
+        let script = ScriptSpec::from(diff.clone()).and_then(CommandSpec::new("echo").arg("done"));
+        let got = PosixRenderer::default().render_script(&script).unwrap();
+        assert_eq!(got, "{ diff 'a' 'b' && sx_s=0 || sx_s=$?; case $sx_s in 0|1) ;; *) (exit $((sx_s ? sx_s : 1))) ;; esac; } && echo 'done'");
+        assert_eq!(
+            PosixRenderer::default().render_script_plan(&script).unwrap(),
+            "diff 'a' 'b' && echo 'done'  # diff: ok_status=0|1"
+        );
+        assert!(WinRenderer::default().render_script(&script).is_err());
+        // a status left out of the set fails, even 0
+        let grep_v = CommandSpec::new("grep").args(["-q", "err", "log"]).success_statuses([1]);
+        assert_eq!(PosixRenderer::default().render_script(&ScriptSpec::from(grep_v).or_else(CommandSpec::new("alert"))).unwrap(),
+            "{ grep '-q' 'err' 'log' && sx_s=0 || sx_s=$?; case $sx_s in 1) ;; *) (exit $((sx_s ? sx_s : 1))) ;; esac; } || alert");
+        assert_eq!(WinRenderer::default().render_script_plan(&script).unwrap(), "diff a b && echo done & rem diff: ok_status=0|1");
+        let bg = CommandSpec::new("serve").timeout(std::time::Duration::from_secs(1)).background();
+        assert_eq!(WinRenderer::default().render_pipe_plan(&bg.into()).unwrap(), "serve & rem serve: timeout=1000ms");
+        // through the trait, piped stages are escaped twice and notes come last
+        let pipe = CommandSpec::new("app").arg("a&b").timeout(std::time::Duration::from_secs(1)).pipe(CommandSpec::new("more"));
+        let want = "app a^^^&b | more & rem app: timeout=1000ms";
+        let dynamic: &dyn Renderer = &WinRenderer::default();
+        assert_eq!(dynamic.render_pipe_plan(&pipe).unwrap(), want);
+        assert_eq!(crate::exec::Planner::new(&WinRenderer::default()).plan(&pipe).unwrap(), want);
+        // a lone command keeps its own status
+        assert_eq!(PosixRenderer::default().render_cmd(&diff).unwrap(), "diff 'a' 'b'");
+
+        let probe = ScriptSpec::from(CommandSpec::new("curl").stdout_non_empty()).and_then(CommandSpec::new("true"));
+        assert!(PosixRenderer::default().render_script(&probe).is_err());
+    }
+
+    #[test]
+    fn plans_and_debug_redact_secrets() {
+        let cmd = CommandSpec::new("curl")
+            .secret_env("API_TOKEN", "s3cr3t")
+            .arg("-H")
+            .arg("Authorization: Bearer s3cr3t")
+            .secret_arg("hunter2");
+        assert_eq!(
+            PosixRenderer::default().render_cmd_plan(&cmd).unwrap(),
+            "API_TOKEN='***' curl '-H' 'Authorization: Bearer ***' '***'"
+        );
+        assert_eq!(
+            PosixRenderer::default().render_cmd(&cmd).unwrap(),
+            "API_TOKEN='s3cr3t' curl '-H' 'Authorization: Bearer s3cr3t' 'hunter2'"
+        );
+        let pipe = cmd.clone().pipe(CommandSpec::new("jq"));
+        let planned = [
+            PosixRenderer::default().render_pipe_plan(&pipe).unwrap(),
+            WinRenderer::default().render_pipe_plan(&pipe).unwrap(),
+            WinRenderer::default().render_cmd_plan(&cmd).unwrap(),
+            PosixRenderer::default().render_script_plan(&ScriptSpec::from(pipe.clone())).unwrap(),
+            format!("{:?}", pipe),
+        ];
+        for p in planned {
+            assert!(!p.contains("s3cr3t") && !p.contains("hunter2") && p.contains("***"), "{}", p);
+        }
+
+        let login = CommandSpec::new("login").secret("pa55").stdin_text("user\npa55\n").stderr_to("/tmp/pa55.log");
+        assert_eq!(
+            PosixRenderer::default().render_cmd_plan(&login).unwrap(),
+            "login <<'SX_EOF' 2> '/tmp/***.log'\nuser\n***\nSX_EOF"
+        );
+        let bash = PosixRenderer { bash: true, ..Default::default() };
+        let piped = CommandSpec::new("/opt/pa55/bin/tool").secret("pa55").stdin(Stdio::Bytes(b"pa55\xff".to_vec())).stdout_to("pa55");
+        let planned = [
+            bash.render_cmd_plan(&login).unwrap(),
+            bash.render_pipe_plan(&piped.clone().into()).unwrap(),
+            WinRenderer::default().render_cmd_plan(&login).unwrap(),
+            WinRenderer::default().render_cmd_plan(&piped.clone().stdin(Stdio::Bytes(b"pa55\n".to_vec()))).unwrap(),
+            format!("{:?}", piped),
+        ];
+        for p in planned {
+            assert!(!p.contains("pa55") && p.contains("***"), "{}", p);
+        }
+    }
+
+    #[test]
+    fn render_env_removal_and_clear() {
+        let cmd = CommandSpec::new("make").env_remove("LD_PRELOAD").env("CC", "clang");
+        assert_eq!(PosixRenderer::default().render_cmd(&cmd).unwrap(), "env -u LD_PRELOAD CC='clang' make");
+        assert_eq!(WinRenderer::default().render_cmd(&cmd).unwrap(), "setlocal && set \"LD_PRELOAD=\" && set \"CC=clang\" && make & endlocal");
+
+        let hermetic = CommandSpec::new("cargo").arg("build").env_clear().env_keep("PATH").env_keep("HOME");
+        assert_eq!(
+            PosixRenderer::default().render_cmd(&hermetic).unwrap(),
+            "env -i ${HOME+\"HOME=$HOME\"} ${PATH+\"PATH=$PATH\"} cargo 'build'"
+        );
+        assert_eq!(
+            WinRenderer::default().render_cmd(&hermetic).unwrap(),
+            "setlocal && (for /f \"delims==\" %V in ('set') do @if /i not \"%V\"==\"HOME\" if /i not \"%V\"==\"PATH\" set \"%V=\") && cargo build & endlocal"
+        );
+        assert!(WinRenderer { batch: true, ..Default::default() }.render_cmd(&hermetic).unwrap()
+            .starts_with("setlocal && (for /f \"delims==\" %%V in ('set') do @if /i not \"%%V\"==\"HOME\" "));
+
+        // bracketed, so `&& deploy` does not bind to `endlocal` alone
+        let script = ScriptSpec::from(cmd).and_then(CommandSpec::new("deploy"));
+        assert_eq!(
+            WinRenderer::default().render_script(&script).unwrap(),
+            "(setlocal && set \"LD_PRELOAD=\" && set \"CC=clang\" && make & endlocal) && deploy"
+        );
+    }
+
     #[test]
     fn render_pipe_plan_with_flags() {
         let mut p = PipelineSpec::new();
         let mut c1 = CommandSpec { program: "echo".into(), args: vec!["a".into()], ..Default::default() };
         c1.flags.timeout_ms = Some(500);
         let mut c2 = CommandSpec { program: "grep".into(), args: vec!["b".into()], ..Default::default() };
-        c2.flags.retries = 2;
+        c2.flags.retry.max_attempts = 3;
         c2.flags.background = true;
         p.push(c1);
         p.push(c2);
@@ -278,9 +1379,151 @@ mod tests {
             stdout: Stdio::File { path: "out log.txt".into(), append: false },
             stderr: Stdio::File { path: "err.log".into(), append: true },
             flags: Default::default(),
+            ..Default::default()
         };
         let r = WinRenderer::default();
         let got = r.render_cmd(&cmd).unwrap();
-        assert_eq!(got, "cd /d \"C:\\Work Dir\" && set \"APPDATA=C:\\Data\\App\" && \"C:\\Program Files\\MyApp\\app.exe\" \"hello world\" \"a\"\"b\" < NUL > \"out log.txt\" 2>> err.log");
+        assert_eq!(got, "setlocal && cd /d \"C:\\Work Dir\" && set \"APPDATA=C:\\Data\\App\" && \"C:\\Program Files\\MyApp\\app.exe\" \"hello world\" ^\"a\\^\"b^\" < NUL > \"out log.txt\" 2>> err.log & endlocal");
+    }
+
+    /// cmd.exe's pass over a fragment: `%VAR%` expansion, then carets and
+    /// quotes. Unescaped operators or an unclosed quote fail the test.
+    fn cmd_parse(frag: &str, batch: bool, vars: &[(&str, &str)]) -> String {
+        let chars: Vec<char> = frag.chars().collect();
+        let mut expanded = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] != '%' { expanded.push(chars[i]); i += 1; continue; }
+            if batch && chars.get(i + 1) == Some(&'%') { expanded.push('%'); i += 2; continue; }
+            let Some(n) = chars[i + 1..].iter().position(|&c| c == '%') else {
+                if !batch { expanded.push('%'); }
+                i += 1;
+                continue;
+            };
+            let name: String = chars[i + 1..i + 1 + n].iter().collect();
+            let base = name.split(':').next().unwrap();
+            match vars.iter().find(|(k, _)| k.eq_ignore_ascii_case(base)) {
+                Some((_, v)) => { expanded.push_str(v); i += n + 2; }
+                None if batch => i += n + 2,
+                None => { expanded.push('%'); i += 1; }
+            }
+        }
+        let mut out = String::new();
+        let mut quoted = false;
+        let mut it = expanded.chars();
+        while let Some(c) = it.next() {
+            match c {
+                '"' => { quoted = !quoted; out.push(c); }
+                '^' if !quoted => out.push(it.next().expect("dangling caret")),
+                '&' | '|' | '<' | '>' | '(' | ')' if !quoted => panic!("unescaped {:?} in {}", c, frag),
+                _ => out.push(c),
+            }
+        }
+        assert!(!quoted, "unclosed quote in {}", frag);
+        out
+    }
+
+    /// The MSVC runtime's split of a command line into argv.
+    fn argv_parse(line: &str) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        let (mut args, mut i) = (Vec::new(), 0);
+        loop {
+            while i < chars.len() && matches!(chars[i], ' ' | '\t') { i += 1; }
+            if i >= chars.len() { return args; }
+            let (mut arg, mut quoted) = (String::new(), false);
+            loop {
+                let n = chars[i..].iter().take_while(|&&c| c == '\\').count();
+                i += n;
+                if chars.get(i) == Some(&'"') {
+                    arg.extend(std::iter::repeat_n('\\', n / 2));
+                    if n % 2 == 1 { arg.push('"'); } else { quoted = !quoted; }
+                    i += 1;
+                    continue;
+                }
+                arg.extend(std::iter::repeat_n('\\', n));
+                match chars.get(i) {
+                    None => break,
+                    Some(' ' | '\t') if !quoted => break,
+                    Some(&c) => { arg.push(c); i += 1; }
+                }
+            }
+            args.push(arg);
+        }
+    }
+
+    #[test]
+    fn win_escaping_survives_cmd_and_msvcrt() {
+        // (value, command line, batch file)
+        let table = [
+            ("plain", "plain", "plain"),
+            ("", "\"\"", "\"\""),
+            ("a b", "\"a b\"", "\"a b\""),
+            ("a&b", "a^&b", "a^&b"),
+            ("a & b", "\"a & b\"", "\"a & b\""),
+            ("a\"b", "^\"a\\^\"b^\"", "^\"a\\^\"b^\""),
+            ("C:\\dir with space\\", "\"C:\\dir with space\\\\\"", "\"C:\\dir with space\\\\\""),
+            ("100%", "100^%", "100%%"),
+            ("%PATH%", "^%^PATH^%", "%%PATH%%"),
+            ("%PATH:a=b%", "^%^PATH:a=b^%", "%%PATH:a=b%%"),
+            ("50% off", "^\"50^%^ off^\"", "\"50%% off\""),
+            ("^(x)|<y>", "^^^(x^)^|^<y^>", "^^^(x^)^|^<y^>"),
+            ("\" & calc & \"", "^\"\\^\" ^& calc ^& \\^\"^\"", "^\"\\^\" ^& calc ^& \\^\"^\""),
+        ];
+        for (value, line, bat) in table {
+            for (batch, want) in [(false, line), (true, bat)] {
+                let r = WinRenderer { batch, ..Default::default() };
+                let got = r.render_cmd(&CommandSpec::new("app").arg(value)).unwrap();
+                assert_eq!(got, format!("app {}", want), "{:?} batch={}", value, batch);
+            }
+        }
+        // each side of a `|` is parsed once more, by a child cmd.exe on its command line
+        let piped = [
+            ("a&b", "a^^^&b", "a^^^&b"),
+            ("a & b", "\"a & b\"", "\"a & b\""),
+            ("a\"b", "^^^\"a\\^^^\"b^^^\"", "^^^\"a\\^^^\"b^^^\""),
+            ("100%", "100^^^%", "100^^%%"),
+            ("%PATH%", "^^^%^^PATH^^^%", "^^%%^^PATH^^%%"),
+        ];
+        for (value, line, bat) in piped {
+            for (batch, want) in [(false, line), (true, bat)] {
+                let r = WinRenderer { batch, ..Default::default() };
+                let got = r.render_pipe(&CommandSpec::new("app").arg(value).pipe(CommandSpec::new("more"))).unwrap();
+                assert_eq!(got, format!("app {} | more", want), "{:?} batch={}", value, batch);
+            }
+        }
+
+        let vars = [("PATH", "C:\\Windows"), ("X", "boom"), ("P", "p")];
+        let nasty = [
+            "", " ", "a b", "a\"b", "\"", "\"\"", "a\\", "a\\\\", "a\\\"b", "\\\\server\\share\\", "a&b", "a|b", "a<b>c", "(x)",
+            "^", "^^", "a^&b", "100%", "%PATH%", "%X%%X%", "%PATH:~1%", "%PATH:a=b%", "%%", "% %", "\"%X%\"",
+            "a\"&\"b", "!X!", "tab\there", "&& echo pwned", "\" & calc & \"", "%X%\"&%X%", "%\"X\"%",
+        ];
+        for batch in [false, true] {
+            for a in nasty {
+                let frag = escape_cmd(&quote_argv_win(a), batch).unwrap();
+                assert_eq!(argv_parse(&cmd_parse(&frag, batch, &vars)), [a], "{:?} as {} batch={}", a, frag, batch);
+                // `set "V=…"` sees cmd.exe's pass only and drops the outer quotes
+                let frag = escape_cmd(&format!("\"V={}\"", a), batch).unwrap();
+                let seen = cmd_parse(&frag, batch, &vars);
+                assert_eq!(&seen[1..seen.rfind('"').unwrap()], format!("V={}", a), "{:?} as {} batch={}", a, frag, batch);
+
+                let frag = escape_cmd_at(&quote_argv_win(a), batch, 1).unwrap();
+                let seen = cmd_parse(&cmd_parse(&frag, batch, &vars), false, &vars);
+                assert_eq!(argv_parse(&seen), [a], "{:?} piped as {} batch={}", a, frag, batch);
+                let frag = escape_cmd_at(&format!("\"V={}\"", a), batch, 1).unwrap();
+                let seen = cmd_parse(&cmd_parse(&frag, batch, &vars), false, &vars);
+                assert_eq!(&seen[1..seen.rfind('"').unwrap()], format!("V={}", a), "{:?} piped as {} batch={}", a, frag, batch);
+                // an echoed stdin line
+                let frag = escape_cmd_at(a, batch, 1).unwrap();
+                assert_eq!(cmd_parse(&cmd_parse(&frag, batch, &vars), false, &vars), a, "echo({:?} as {} batch={}", a, frag, batch);
+            }
+        }
+
+        let r = WinRenderer::default();
+        assert!(r.render_cmd(&CommandSpec::new("app").arg("a\nb")).is_err());
+        assert!(r.render_cmd(&CommandSpec::new("app").stdout_to("50% done.log")).is_err());
+        let bat = WinRenderer { batch: true, ..Default::default() };
+        assert_eq!(bat.render_cmd(&CommandSpec::new("app").stdout_to("50% done.log").env("P", "%X% & y")).unwrap(),
+            "setlocal && set \"P=%%X%% & y\" && app > \"50%% done.log\" & endlocal");
     }
 }
@@ -0,0 +1,345 @@
+//! Nushell rendering.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+
+use super::{check_dup, in_memory_output, plan_meta, stage_note, with_notes, QuotePolicy, Renderer};
+use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec, Stdio};
+use crate::error::SyntaxError;
+
+/// Renders for Nushell (0.91 or later). Programs are called with `^` so a
+/// Nushell builtin of the same name (`ls`, `sort`, …) is never picked, and
+/// every argument is a quoted string, which Nushell never globs.
+///
+/// Env goes through `with-env { K: 'v' } { … }`; `cwd` and env removal use
+/// `cd`/`hide-env` inside `do { … }`. Both are scoped to the block. Stdin is
+/// piped in (`open --raw f | ^cmd`, `0x[…]` for binary data), stderr uses
+/// `e>`/`o+e>`, and background plans show `job spawn { … }`.
+///
+/// Nushell has no `&&`/`||`: lists check `$env.LAST_EXIT_CODE`, so
+/// `a && b` becomes `a; if $env.LAST_EXIT_CODE == 0 { b }`, and groups and
+/// subshells are flattened (nothing a stage sets outlives its block).
+#[derive(Debug, Clone)]
+pub struct NuRenderer {
+    /// Ignored: Nushell arguments are always quoted strings.
+    #[deprecated(note = "has no effect; Nushell arguments are always quoted strings")]
+    pub quote: QuotePolicy,
+}
+
+#[allow(deprecated)]
+impl Default for NuRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+
+impl Renderer for NuRenderer {
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        if cmd.flags.has_process_attrs() {
+            return Err(SyntaxError::RenderError("nice/umask/rlimits cannot be applied by Nushell".into()));
+        }
+        self.render_line(cmd, false, true)
+    }
+
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        Ok(format!("{}{}", self.render_line(&cmd.redacted(), false, true)?, plan_meta(cmd, true)))
+    }
+
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        self.render_pipe_line(pipe, false, false, &mut Vec::new())
+    }
+
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let s = self.render_pipe_line(pipe, true, false, &mut notes)?;
+        Ok(with_notes(s, notes))
+    }
+
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        self.render_list(script, false, &mut Vec::new())
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let s = self.render_list(script, true, &mut notes)?;
+        Ok(with_notes(s, notes))
+    }
+}
+
+impl NuRenderer {
+    fn render_list(&self, script: &ScriptSpec, plan: bool, notes: &mut Vec<String>) -> Result<String, SyntaxError> {
+        let mut list = |s: &ScriptSpec| self.render_list(s, plan, notes);
+        Ok(match script {
+            ScriptSpec::Pipe(p) => {
+                if p.is_empty() { return Err(SyntaxError::RenderError("empty pipeline in script".into())); }
+                self.render_pipe_line(p, plan, true, notes)?
+            }
+            ScriptSpec::Subshell(inner) | ScriptSpec::Group(inner) => list(inner)?,
+            ScriptSpec::AndThen(a, b) => format!("{}; if $env.LAST_EXIT_CODE == 0 {{ {} }}", list(a)?, list(b)?),
+            ScriptSpec::OrElse(a, b) => format!("{}; if $env.LAST_EXIT_CODE != 0 {{ {} }}", list(a)?, list(b)?),
+            ScriptSpec::Always(a, b) => format!("{}; {}", list(a)?, list(b)?),
+        })
+    }
+
+    /// Plans collect each stage's plan notes in `notes`.
+    fn render_pipe_line(&self, pipe: &PipelineSpec, plan: bool, in_list: bool, notes: &mut Vec<String>) -> Result<String, SyntaxError> {
+        if pipe.opts.pipefail && pipe.stages.len() > 1 {
+            return Err(SyntaxError::RenderError("pipefail is not supported by Nushell".into()));
+        }
+        if !plan && in_list && pipe.stages.last().is_some_and(|c| c.success.is_custom()) {
+            return Err(SyntaxError::RenderError("custom success criteria cannot be rendered for Nushell lists".into()));
+        }
+        let n = pipe.stages.len();
+        let mut s = String::new();
+        for (i, c) in pipe.effective_stages()?.enumerate() {
+            let c = if plan { Cow::Owned(c.redacted()) } else { c };
+            if !plan && c.flags.has_process_attrs() {
+                return Err(SyntaxError::RenderError("nice/umask/rlimits cannot be applied by Nushell".into()));
+            }
+            let last = i + 1 == n;
+            s.push_str(&self.render_line(&c, i > 0, last)?);
+            if plan { notes.extend(stage_note(&c, true)); }
+            if !last {
+                // `2>&1` into the pipe is `o+e>|`
+                s.push_str(if matches!(c.stderr, Stdio::Dup(_)) { " o+e>| " } else { " | " });
+            }
+        }
+        if plan && pipe.stages.last().is_some_and(|c| c.flags.background) {
+            s = format!("job spawn {{ {} }}", s);
+        }
+        Ok(s)
+    }
+
+    /// Render one command. `isolate` puts a later stage with its own stdin in
+    /// a block so its source does not take the pipeline's input; `last` says
+    /// whether stdout goes on down a pipe.
+    fn render_line(&self, cmd: &CommandSpec, isolate: bool, last: bool) -> Result<String, SyntaxError> {
+        if cmd.program.is_empty() {
+            return Err(SyntaxError::RenderError("program empty".into()));
+        }
+        let mut parts: Vec<String> = Vec::new();
+
+        // stdin comes down the Nushell pipeline
+        let src = match (&cmd.stdin, cmd.stdin.in_memory()) {
+            (_, Some(data)) => Some(nu_data(data)),
+            (Stdio::Null, _) => Some("''".to_string()),
+            (Stdio::File { path, .. }, _) => Some(format!("open --raw {}", quote_nu(nu_str(path)?))),
+            (io @ Stdio::Dup(_), _) => { check_dup(0, io)?; None }
+            _ => None,
+        };
+
+        parts.push(format!("^{}", quote_prog_nu(nu_str(&cmd.program)?)));
+        for a in &cmd.args { parts.push(quote_nu(nu_str(a)?)); }
+
+        match (&cmd.stdout, &cmd.stderr) {
+            (Stdio::Dup(_), _) => {
+                check_dup(1, &cmd.stdout)?;
+                return Err(SyntaxError::RenderError("Nushell cannot redirect stdout to stderr".into()));
+            }
+            (out, err @ Stdio::Dup(_)) => {
+                check_dup(2, err)?;
+                // merged into the pipe by the caller; on a terminal both already meet
+                match out {
+                    Stdio::File { path, append } => parts.push(format!("o+e>{} {}", if *append { ">" } else { "" }, quote_nu(nu_str(path)?))),
+                    Stdio::Null => parts.push("o+e> /dev/null".to_string()),
+                    _ => {}
+                }
+            }
+            (out, err) => {
+                if let Some(r) = redir_nu("o", out)? { parts.push(r); }
+                if let Some(r) = redir_nu("e", err)? { parts.push(r); }
+            }
+        }
+        if !last && matches!(cmd.stdout, Stdio::File { .. } | Stdio::Null) {
+            return Err(SyntaxError::RenderError("Nushell cannot redirect stdout of a piped stage".into()));
+        }
+        let mut line = parts.join(" ");
+
+        if !cmd.env.is_empty() {
+            let mut rec = Vec::new();
+            for (k, v) in &cmd.env { rec.push(format!("{}: {}", k, quote_nu(nu_str(v)?))); }
+            line = format!("with-env {{ {} }} {{ {} }}", rec.join(", "), line);
+        }
+        if let Some(src) = &src { line = format!("{} | {}", src, line); }
+
+        // cwd and env removal are scoped to a `do` block
+        let mut pre: Vec<String> = Vec::new();
+        if let Some(dir) = &cmd.cwd { pre.push(format!("cd {}", quote_nu(nu_str(dir)?))); }
+        if cmd.env_clear {
+            let keep: Vec<String> = ["PWD"].into_iter().chain(cmd.env_keep.iter().map(String::as_str)).map(quote_nu).collect();
+            pre.push(format!("hide-env -i ...($env | columns | where $it not-in [{}])", keep.join(", ")));
+        }
+        if !cmd.env_remove.is_empty() {
+            let names: Vec<&str> = cmd.env_remove.iter().map(String::as_str).collect();
+            pre.push(format!("hide-env -i {}", names.join(" ")));
+        }
+        if !pre.is_empty() || (isolate && src.is_some()) {
+            pre.push(line);
+            line = format!("do {{ {} }}", pre.join("; "));
+        }
+        Ok(line)
+    }
+}
+
+fn redir_nu(stream: &str, io: &Stdio) -> Result<Option<String>, SyntaxError> {
+    Ok(match io {
+        Stdio::Inherit | Stdio::Pipe | Stdio::Dup(_) => None,
+        Stdio::Null => Some(format!("{}> /dev/null", stream)),
+        Stdio::File { path, append } => Some(format!("{}>{} {}", stream, if *append { ">" } else { "" }, quote_nu(nu_str(path)?))),
+        Stdio::Bytes(_) | Stdio::Text(_) => return Err(in_memory_output(if stream == "o" { 1 } else { 2 })),
+    })
+}
+
+/// In-memory stdin as a Nushell value: a string, or a `0x[…]` binary literal
+/// when the data is not UTF-8.
+fn nu_data(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(text) => quote_nu(text),
+        Err(_) => {
+            let mut out = String::from("0x[");
+            for (i, b) in data.iter().enumerate() {
+                let _ = write!(out, "{}{:02x}", if i > 0 { " " } else { "" }, b);
+            }
+            out.push(']');
+            out
+        }
+    }
+}
+
+/// `'…'` when possible (no escapes at all inside); otherwise `"…"` with
+/// backslash escapes.
+fn quote_nu(s: &str) -> String {
+    if !s.contains('\'') && !s.chars().any(char::is_control) { return format!("'{}'", s); }
+    let mut out = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => { let _ = write!(out, "\\u{{{:x}}}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn quote_prog_nu(p: &str) -> String {
+    let simple = p.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '+'));
+    if simple && !p.starts_with('-') { p.to_string() } else { quote_nu(p) }
+}
+
+/// Nushell strings are UTF-8.
+fn nu_str(s: &OsStr) -> Result<&str, SyntaxError> {
+    s.to_str().ok_or_else(|| SyntaxError::RenderError("non-Unicode value cannot be rendered for Nushell".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nu_render_quoting_env_and_redirs() {
+        let r = NuRenderer::default();
+        let cmd = CommandSpec::new("grep").args(["it's", "a\\b", "$x (y) *", "tab\there"]).env("LANG", "C").cwd("/w d")
+            .stdin_from("in.txt").stdout_append("out file").stderr_to_stdout();
+        assert_eq!(r.render_cmd(&cmd).unwrap(),
+            r#"do { cd '/w d'; open --raw 'in.txt' | with-env { LANG: 'C' } { ^grep "it's" 'a\b' '$x (y) *' "tab\there" o+e>> 'out file' } }"#);
+
+        let c = CommandSpec::new("sort").stdout_to("o.txt").stderr(Stdio::Null).env_clear().env_keep("PATH").env_remove("X");
+        assert_eq!(r.render_cmd(&c).unwrap(),
+            "do { hide-env -i ...($env | columns | where $it not-in ['PWD', 'PATH']); hide-env -i X; ^sort o> 'o.txt' e> /dev/null }");
+        assert!(r.render_cmd(&CommandSpec::new("x").stdout(Stdio::Dup(2))).is_err());
+        assert!(r.render_cmd(&CommandSpec::new("x").nice(5)).is_err());
+    }
+
+    #[test]
+    fn nu_render_pipes_and_lists() {
+        let r = NuRenderer::default();
+        let pipe = CommandSpec::new("make").stdin(Stdio::Bytes(b"a\xff".to_vec()))
+            .pipe(CommandSpec::new("tee").arg("log").stdin_text("x"))
+            .pipe(CommandSpec::new("wc"))
+            .merge_stderr();
+        assert_eq!(r.render_pipe(&pipe).unwrap(), "0x[61 ff] | ^make o+e>| do { 'x' | ^tee 'log' } o+e>| ^wc");
+        assert!(r.render_pipe(&pipe.clone().pipefail()).is_err());
+
+        let c = |p: &str| CommandSpec::new(p);
+        let script = ScriptSpec::from(c("a")).or_else(c("b")).group().and_then(c("c"));
+        assert_eq!(r.render_script(&script).unwrap(),
+            "^a; if $env.LAST_EXIT_CODE != 0 { ^b }; if $env.LAST_EXIT_CODE == 0 { ^c }");
+        let plan = ScriptSpec::from(c("deploy").secret_arg("s3cr3t").background());
+        assert_eq!(r.render_script_plan(&plan).unwrap(), "job spawn { ^deploy '***' }");
+        // notes come last, so the plan still runs as pasted
+        let timed = c("a").timeout(std::time::Duration::from_secs(1));
+        assert_eq!(r.render_script_plan(&ScriptSpec::from(timed.clone()).and_then(c("b"))).unwrap(),
+            "^a; if $env.LAST_EXIT_CODE == 0 { ^b }  # a: timeout=1000ms");
+        assert_eq!(r.render_pipe_plan(&timed.clone().pipe(c("b"))).unwrap(), "^a | ^b  # a: timeout=1000ms");
+        assert_eq!(r.render_pipe_plan(&timed.background().into()).unwrap(), "job spawn { ^a }  # a: timeout=1000ms");
+    }
+
+    /// Run a rendered list as Nushell would, with each program exiting with
+    /// `status(name)`; returns the programs run. `$env.LAST_EXIT_CODE` is
+    /// only set by programs that run, so a skipped block leaves it alone.
+    fn run_nu(line: &str, status: &dyn Fn(&str) -> i32) -> Vec<String> {
+        fn block<'a>(toks: &mut impl Iterator<Item = &'a str>, run: bool, code: &mut i32, ran: &mut Vec<String>, status: &dyn Fn(&str) -> i32) {
+            while let Some(t) = toks.next() {
+                match t {
+                    "}" => return,
+                    ";" => {}
+                    "if" => {
+                        let cond: Vec<&str> = toks.by_ref().take(4).collect();
+                        let taken = match cond[..] {
+                            ["$env.LAST_EXIT_CODE", "==", "0", "{"] => *code == 0,
+                            ["$env.LAST_EXIT_CODE", "!=", "0", "{"] => *code != 0,
+                            _ => panic!("unexpected condition {:?}", cond),
+                        };
+                        block(toks, run && taken, code, ran, status);
+                    }
+                    prog => if run {
+                        let prog = prog.strip_prefix('^').expect("external call");
+                        *code = status(prog);
+                        ran.push(prog.to_string());
+                    },
+                }
+            }
+        }
+        let spaced = line.replace(';', " ; ");
+        let (mut code, mut ran) = (0, Vec::new());
+        block(&mut spaced.split_whitespace(), true, &mut code, &mut ran, status);
+        ran
+    }
+
+    /// The same list under POSIX `&&`/`||`/`;`; returns the status.
+    fn run_sh(script: &ScriptSpec, status: &dyn Fn(&str) -> i32, ran: &mut Vec<String>) -> i32 {
+        match script {
+            ScriptSpec::Pipe(p) => {
+                let prog = p.stages[0].program.to_str().unwrap();
+                ran.push(prog.to_string());
+                status(prog)
+            }
+            ScriptSpec::Subshell(inner) | ScriptSpec::Group(inner) => run_sh(inner, status, ran),
+            ScriptSpec::AndThen(a, b) => match run_sh(a, status, ran) { 0 => run_sh(b, status, ran), s => s },
+            ScriptSpec::OrElse(a, b) => match run_sh(a, status, ran) { 0 => 0, _ => run_sh(b, status, ran) },
+            ScriptSpec::Always(a, b) => { run_sh(a, status, ran); run_sh(b, status, ran) }
+        }
+    }
+
+    #[test]
+    fn nu_lists_chain_on_last_exit_code_like_posix() {
+        let c = |p: &str| ScriptSpec::from(CommandSpec::new(p));
+        let scripts = [
+            c("a").and_then(c("b").or_else(c("c"))).and_then(c("d")),
+            c("a").or_else(c("b").and_then(c("c").or_else(c("d")))),
+            c("a").and_then(c("b")).or_else(c("c")).subshell().then(c("d")).or_else(c("e")),
+            c("a").or_else(c("b")).group().and_then(c("c").and_then(c("d")).subshell()).or_else(c("e")),
+        ];
+        let r = NuRenderer::default();
+        for script in &scripts {
+            let line = r.render_script(script).unwrap();
+            for failing in 0..32 {
+                let status = |p: &str| (failing >> (p.as_bytes()[0] - b'a')) & 1;
+                let mut want = Vec::new();
+                run_sh(script, &status, &mut want);
+                assert_eq!(run_nu(&line, &status), want, "{} with failing mask {:05b}", line, failing);
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! PowerShell rendering.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+
+use super::{check_dup, in_memory_output, plan_meta, quote_argv_win, render_list, stage_note, with_notes, ListSyntax, QuotePolicy, Renderer};
+use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec, Stdio};
+use crate::error::SyntaxError;
+
+// `&&`/`||` need PowerShell 7. `& { }` runs in a child scope; `. { }` in the
+// current one.
+const PS_LIST: ListSyntax = ListSyntax {
+    and: " && ", or: " || ", seq: "; ",
+    subshell: ("& { ", " }"), group: (". { ", " }"),
+    group_all: false,
+};
+
+/// Renders for PowerShell (5.1 and 7). Arguments are single-quoted, so
+/// nothing in them is expanded.
+///
+/// PowerShell before 7.3 passes empty arguments and embedded `"` to native
+/// programs incorrectly. Commands with such arguments use the stop-parsing
+/// token `--%` and a Windows command line quoted for the MSVC runtime; that
+/// token runs to the end of the line, so such commands cannot be followed by
+/// a list operator, and their arguments may not contain `%`, `|` or newlines.
+///
+/// PowerShell has no per-command environment or working directory: `env` and
+/// `cwd` are set inside `& { … }` and restored in a `finally` block.
+#[derive(Debug, Clone)]
+pub struct PowerShellRenderer {
+    /// Ignored: PowerShell arguments are always single-quoted.
+    #[deprecated(note = "has no effect; PowerShell arguments are always single-quoted")]
+    pub quote: QuotePolicy,
+}
+
+#[allow(deprecated)]
+impl Default for PowerShellRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+
+impl Renderer for PowerShellRenderer {
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        if cmd.flags.has_process_attrs() {
+            return Err(SyntaxError::RenderError("nice/umask/rlimits cannot be applied by PowerShell".into()));
+        }
+        Ok(self.render_line(cmd, false)?.0)
+    }
+
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> {
+        Ok(format!("{}{}", self.render_line(&cmd.redacted(), false)?.0, plan_meta(cmd, true)))
+    }
+
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        self.render_pipe_line(pipe, false, false, &mut Vec::new())
+    }
+
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let s = self.render_pipe_line(pipe, true, false, &mut notes)?;
+        Ok(with_notes(s, notes))
+    }
+
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let (s, _) = render_list(script, &PS_LIST, &mut |p| Ok((self.render_pipe_line(p, false, true, &mut Vec::new())?, Vec::new())))?;
+        Ok(s)
+    }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> {
+        let mut notes = Vec::new();
+        let (s, _) = render_list(script, &PS_LIST, &mut |p| Ok((self.render_pipe_line(p, true, true, &mut notes)?, Vec::new())))?;
+        Ok(with_notes(s, notes))
+    }
+}
+
+impl PowerShellRenderer {
+    /// Plans collect each stage's plan notes in `notes`.
+    fn render_pipe_line(&self, pipe: &PipelineSpec, plan: bool, in_list: bool, notes: &mut Vec<String>) -> Result<String, SyntaxError> {
+        if pipe.opts.pipefail && pipe.stages.len() > 1 {
+            return Err(SyntaxError::RenderError("pipefail is not supported by PowerShell".into()));
+        }
+        if !plan && in_list && pipe.stages.last().is_some_and(|c| c.success.is_custom()) {
+            return Err(SyntaxError::RenderError("custom success criteria cannot be rendered for PowerShell lists".into()));
+        }
+        let mut parts = Vec::new();
+        let mut verbatim = false;
+        for c in pipe.effective_stages()? {
+            let c = if plan { Cow::Owned(c.redacted()) } else { c };
+            if !plan && c.flags.has_process_attrs() {
+                return Err(SyntaxError::RenderError("nice/umask/rlimits cannot be applied by PowerShell".into()));
+            }
+            let (s, v) = self.render_line(&c, !parts.is_empty())?;
+            if plan { notes.extend(stage_note(&c, true)); }
+            parts.push(s);
+            verbatim = v;
+        }
+        if verbatim && in_list && !plan {
+            return Err(SyntaxError::RenderError("a command using --% cannot be followed by a list operator".into()));
+        }
+        let mut s = parts.join(" | ");
+        if plan {
+            if let Some(last) = pipe.stages.last() { if last.flags.background { s.push_str(" &"); } }
+        }
+        Ok(s)
+    }
+
+    /// Render one command; the flag is set when the line ends in `--%`
+    /// arguments. A later pipeline stage with its own stdin is put in a
+    /// block (`isolate`) so its source does not take the pipeline's input.
+    /// Otherwise a block hands the pipeline's input on with `$input |`, as a
+    /// native command in a script block gets none of it.
+    fn render_line(&self, cmd: &CommandSpec, isolate: bool) -> Result<(String, bool), SyntaxError> {
+        if cmd.program.is_empty() {
+            return Err(SyntaxError::RenderError("program empty".into()));
+        }
+        let mut parts: Vec<String> = Vec::new();
+
+        // stdin comes down the PowerShell pipeline
+        match (&cmd.stdin, cmd.stdin.in_memory()) {
+            (_, Some(data)) => parts.push(pipe_text(data)?),
+            (Stdio::Null, _) => parts.push("@() |".to_string()),
+            (Stdio::File { path, .. }, _) => parts.push(format!("Get-Content -Raw -LiteralPath {} |", quote_ps(ps_str(path)?))),
+            (io @ Stdio::Dup(_), _) => { check_dup(0, io)?; }
+            _ => {}
+        }
+        let own_stdin = !parts.is_empty();
+
+        parts.push(quote_prog_ps(ps_str(&cmd.program)?));
+
+        let args: Vec<&str> = cmd.args.iter().map(|a| ps_str(a)).collect::<Result<_, _>>()?;
+        let verbatim = args.iter().any(|a| a.is_empty() || a.contains('"'));
+        let mut redirs = Vec::new();
+        if let Some(r) = render_redir_ps(1, &cmd.stdout)? { redirs.push(r); }
+        if let Some(r) = render_redir_ps(2, &cmd.stderr)? { redirs.push(r); }
+        if verbatim {
+            // redirections must come before `--%`, which takes the rest of the line
+            parts.extend(redirs);
+            parts.push("--%".to_string());
+            for a in &args {
+                if a.contains(['%', '|', '\n', '\r']) {
+                    return Err(SyntaxError::RenderError("argument cannot be passed after --% (contains %, | or a newline)".into()));
+                }
+                parts.push(quote_argv_win(a));
+            }
+        } else {
+            parts.extend(args.iter().map(|a| quote_ps(a)));
+            parts.extend(redirs);
+        }
+        let core = parts.join(" ");
+
+        // set env/cwd in a block and put them back afterwards
+        let mut pre: Vec<String> = Vec::new();
+        let mut post: Vec<String> = Vec::new();
+        if let Some(dir) = &cmd.cwd {
+            pre.push(format!("Push-Location -LiteralPath {} -ErrorAction Stop", quote_ps(ps_str(dir)?)));
+        }
+        const RESTORE: &str = "foreach ($k in $sx_env.Keys) { [Environment]::SetEnvironmentVariable($k, $sx_env[$k]) }";
+        if cmd.env_clear {
+            pre.push("$sx_env = @{}; Get-ChildItem env: | ForEach-Object { $sx_env[$_.Name] = $_.Value }".to_string());
+            let keep: Vec<String> = cmd.env_keep.iter().map(|k| quote_ps(k)).collect();
+            pre.push(match keep.is_empty() {
+                true => "Get-ChildItem env: | Remove-Item".to_string(),
+                false => format!("Get-ChildItem env: | Where-Object Name -notin {} | Remove-Item", keep.join(", ")),
+            });
+            post.push(format!("Get-ChildItem env: | Remove-Item; {}", RESTORE));
+        } else if !cmd.env.is_empty() || !cmd.env_remove.is_empty() {
+            let names: Vec<String> = cmd.env_remove.iter().chain(cmd.env.keys()).map(|k| quote_ps(k)).collect();
+            pre.push(format!("$sx_env = @{{}}; foreach ($k in {}) {{ $sx_env[$k] = [Environment]::GetEnvironmentVariable($k) }}", names.join(", ")));
+            post.push(RESTORE.to_string());
+        }
+        for k in &cmd.env_remove { pre.push(format!("$env:{} = $null", k)); }
+        for (k, v) in &cmd.env { pre.push(format!("$env:{} = {}", k, quote_ps(ps_str(v)?))); }
+        if cmd.cwd.is_some() { post.push("Pop-Location".to_string()); }
+        let core = if isolate && !own_stdin && !pre.is_empty() { format!("$input | {}", core) } else { core };
+        let body = if verbatim { format!("{}\n", core) } else { format!("{} ", core) };
+        if pre.is_empty() {
+            if isolate && own_stdin { return Ok((format!("& {{ {}}}", body), false)); }
+            return Ok((core, verbatim));
+        }
+        Ok((format!("& {{ {}; try {{ {}}} finally {{ {} }} }}", pre.join("; "), body, post.join("; ")), false))
+    }
+}
+
+fn render_redir_ps(fd: u8, io: &Stdio) -> Result<Option<String>, SyntaxError> {
+    let op = |append: bool| match (fd, append) { (1, false) => ">", (1, true) => ">>", (_, false) => "2>", (_, true) => "2>>" };
+    Ok(match io {
+        Stdio::Inherit | Stdio::Pipe => None,
+        Stdio::Null => Some(format!("{} $null", op(false))),
+        Stdio::File { path, append } => Some(format!("{} {}", op(*append), quote_ps(ps_str(path)?))),
+        Stdio::Dup(_) if fd == 1 => {
+            check_dup(fd, io)?;
+            return Err(SyntaxError::RenderError("PowerShell cannot redirect stdout to stderr".into()));
+        }
+        Stdio::Dup(_) => { check_dup(fd, io)?; Some("2>&1".to_string()) }
+        Stdio::Bytes(_) | Stdio::Text(_) => return Err(in_memory_output(fd)),
+    })
+}
+
+/// In-memory stdin as a piped string: `'line1<LF>line2' |`. PowerShell
+/// appends the final newline itself; empty input pipes nothing.
+fn pipe_text(data: &[u8]) -> Result<String, SyntaxError> {
+    if data.is_empty() { return Ok("@() |".to_string()); }
+    let text = std::str::from_utf8(data)
+        .map_err(|_| SyntaxError::RenderError("piped stdin is not valid UTF-8".into()))?;
+    Ok(format!("{} |", quote_ps(text.strip_suffix('\n').unwrap_or(text))))
+}
+
+/// Single-quote a value. PowerShell also treats the typographic quotes
+/// `‘ ’ ‚ ‛` as single quotes, so those are doubled too.
+fn quote_ps(s: &str) -> String {
+    let mut out = String::from("'");
+    for ch in s.chars() {
+        if matches!(ch, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') { out.push(ch); }
+        out.push(ch);
+    }
+    out.push('\'');
+    out
+}
+
+/// A bare program name, or `& 'quoted path'` through the call operator.
+fn quote_prog_ps(p: &str) -> String {
+    let simple = !p.starts_with('-') && p.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '\\' | ':'));
+    if simple { p.to_string() } else { format!("& {}", quote_ps(p)) }
+}
+
+fn ps_str(s: &OsStr) -> Result<&str, SyntaxError> {
+    s.to_str().ok_or_else(|| SyntaxError::RenderError("non-Unicode value cannot be rendered for PowerShell".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn ps_render_cmd_env_cwd_args_redirs() {
+        let mut env = BTreeMap::new();
+        env.insert("APPDATA".into(), "C:\\Data\\App".into());
+        let cmd = CommandSpec {
+            program: "C:\\Program Files\\MyApp\\app.exe".into(),
+            args: vec!["hello world".into(), "it's $HOME".into()],
+            env,
+            cwd: Some("C:\\Work Dir".into()),
+            stdin: Stdio::Null,
+            stdout: Stdio::File { path: "out log.txt".into(), append: false },
+            stderr: Stdio::File { path: "err.log".into(), append: true },
+            ..Default::default()
+        };
+        let r = PowerShellRenderer::default();
+        assert_eq!(r.render_cmd(&cmd).unwrap(), concat!(
+            "& { Push-Location -LiteralPath 'C:\\Work Dir' -ErrorAction Stop; ",
+            "$sx_env = @{}; foreach ($k in 'APPDATA') { $sx_env[$k] = [Environment]::GetEnvironmentVariable($k) }; ",
+            "$env:APPDATA = 'C:\\Data\\App'; ",
+            "try { @() | & 'C:\\Program Files\\MyApp\\app.exe' 'hello world' 'it''s $HOME' > 'out log.txt' 2>> 'err.log' } ",
+            "finally { foreach ($k in $sx_env.Keys) { [Environment]::SetEnvironmentVariable($k, $sx_env[$k]) }; Pop-Location } }",
+        ));
+
+        let bare = CommandSpec::new("git").args(["log", "‘x’"]).stderr_to_stdout();
+        assert_eq!(r.render_cmd(&bare).unwrap(), "git 'log' '‘‘x’’' 2>&1");
+        let cleared = CommandSpec::new("tool").env_clear().env_keep("PATH").env_remove("X");
+        assert_eq!(r.render_cmd(&cleared).unwrap(), concat!(
+            "& { $sx_env = @{}; Get-ChildItem env: | ForEach-Object { $sx_env[$_.Name] = $_.Value }; ",
+            "Get-ChildItem env: | Where-Object Name -notin 'PATH' | Remove-Item; $env:X = $null; ",
+            "try { tool } finally { Get-ChildItem env: | Remove-Item; ",
+            "foreach ($k in $sx_env.Keys) { [Environment]::SetEnvironmentVariable($k, $sx_env[$k]) } } }",
+        ));
+        assert!(r.render_cmd(&CommandSpec::new("x").stdout(Stdio::Dup(2))).is_err());
+        assert!(r.render_cmd(&CommandSpec::new("x").nice(5)).is_err());
+    }
+
+    #[test]
+    fn ps_stop_parsing_for_legacy_native_args() {
+        let r = PowerShellRenderer::default();
+        let cmd = CommandSpec::new("app").args(["a\"b", "", "c d\\", "e"]).stdout_to("o.txt");
+        assert_eq!(r.render_cmd(&cmd).unwrap(), r#"app > 'o.txt' --% "a\"b" "" "c d\\" e"#);
+        // the `--%` line ends before the block closes
+        let scoped = cmd.clone().cwd("C:\\w");
+        assert_eq!(r.render_cmd(&scoped).unwrap(),
+            "& { Push-Location -LiteralPath 'C:\\w' -ErrorAction Stop; try { app > 'o.txt' --% \"a\\\"b\" \"\" \"c d\\\\\" e\n} finally { Pop-Location } }");
+        assert!(r.render_cmd(&CommandSpec::new("app").args(["", "100%"])).is_err());
+        let list = ScriptSpec::from(cmd).and_then(CommandSpec::new("next"));
+        assert!(r.render_script(&list).is_err());
+    }
+
+    #[test]
+    fn ps_render_pipes_and_lists() {
+        let r = PowerShellRenderer::default();
+        let c = |p: &str| CommandSpec::new(p);
+        let pipe = c("cat").stdin_text("a\nb\n").pipe(c("sort").stdin_from("x y.txt"));
+        assert_eq!(r.render_pipe(&pipe).unwrap(), "'a\nb' | cat | & { Get-Content -Raw -LiteralPath 'x y.txt' | sort }");
+        assert!(r.render_pipe(&pipe.clone().pipefail()).is_err());
+        let scoped = c("a").pipe(c("b").cwd("C:\\w"));
+        assert_eq!(r.render_pipe(&scoped).unwrap(),
+            "a | & { Push-Location -LiteralPath 'C:\\w' -ErrorAction Stop; try { $input | b } finally { Pop-Location } }");
+        assert_eq!(r.render_cmd(&c("b").cwd("C:\\w")).unwrap(),
+            "& { Push-Location -LiteralPath 'C:\\w' -ErrorAction Stop; try { b } finally { Pop-Location } }");
+
+        let script = ScriptSpec::from(c("build"))
+            .and_then(c("test"))
+            .or_else(ScriptSpec::from(c("notify")).then(c("cleanup")))
+            .then(ScriptSpec::from(c("a")).and_then(c("b")).subshell());
+        assert_eq!(r.render_script(&script).unwrap(), "build && test || . { notify; cleanup }; & { a && b }");
+
+        let plan = c("deploy").arg("--token").secret_arg("s3cr3t").timeout(std::time::Duration::from_secs(3));
+        assert_eq!(r.render_script_plan(&ScriptSpec::from(plan).and_then(c("ok"))).unwrap(),
+            "deploy '--token' '***' && ok  # deploy: timeout=3000ms");
+        let timed = c("a").timeout(std::time::Duration::from_secs(1));
+        assert_eq!(r.render_pipe_plan(&timed.pipe(c("b"))).unwrap(), "a | b  # a: timeout=1000ms");
+    }
+}
@@ -0,0 +1,256 @@
+//! Standalone script files: a POSIX shell script or a cmd.exe batch file.
+
+use super::{PosixRenderer, Renderer, WinRenderer};
+use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec};
+use crate::error::SyntaxError;
+
+/// One step of a [`ScriptFile`]: a pipeline or list, with an optional name
+/// (used for its function or label) and comment.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub script: ScriptSpec,
+}
+
+impl Step {
+    pub fn new(script: impl Into<ScriptSpec>) -> Self { Step { name: None, comment: None, script: script.into() } }
+    pub fn name(mut self, name: impl Into<String>) -> Self { self.name = Some(name.into()); self }
+    pub fn comment(mut self, comment: impl Into<String>) -> Self { self.comment = Some(comment.into()); self }
+}
+
+impl From<ScriptSpec> for Step {
+    fn from(script: ScriptSpec) -> Self { Step::new(script) }
+}
+
+impl From<PipelineSpec> for Step {
+    fn from(pipe: PipelineSpec) -> Self { Step::new(pipe) }
+}
+
+impl From<CommandSpec> for Step {
+    fn from(cmd: CommandSpec) -> Self { Step::new(cmd) }
+}
+
+/// Steps rendered as a complete script to hand to an operator. Each step
+/// becomes a function (`step_<name>`, or `step_<n>` when unnamed) and the
+/// steps run in order, stopping at the first that fails.
+///
+/// ```
+/// use syntax::cmd::CommandSpec;
+/// use syntax::render::{PosixRenderer, ScriptFile, Step};
+///
+/// let script = ScriptFile::new()
+///     .step(Step::new(CommandSpec::new("make").cwd("/src")).name("build").comment("Compile"))
+///     .step(CommandSpec::new("make").arg("test"));
+/// let sh = script.render_sh(&PosixRenderer::default())?;
+/// assert!(sh.starts_with("#!/bin/sh\nset -eu\n"));
+/// assert!(sh.contains("# Compile\nstep_build() {\n  (cd '/src' && make)\n}\n"));
+/// assert!(sh.ends_with("\nstep_build\nstep_2\n"));
+/// # Ok::<(), syntax::SyntaxError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScriptFile { pub steps: Vec<Step> }
+
+impl ScriptFile {
+    pub fn new() -> Self { Self::default() }
+    pub fn step(mut self, step: impl Into<Step>) -> Self { self.steps.push(step.into()); self }
+
+    /// A POSIX shell script: shebang, `set -eu`, and `pipefail` when the
+    /// shell has it. Lines come from `r` with retries rendered as loops and
+    /// timeouts as `timeout N sh -c '…'`; a timed pipeline runs in that
+    /// inner `sh`, which does not inherit the script's `pipefail`. With
+    /// `r.bash` the script is a bash script.
+    pub fn render_sh(&self, r: &PosixRenderer) -> Result<String, SyntaxError> {
+        let r = PosixRenderer { retry_loops: true, timeouts: true, width: None, ..r.clone() };
+        let mut out = if r.bash {
+            String::from("#!/usr/bin/env bash\nset -euo pipefail\n")
+        } else {
+            String::from("#!/bin/sh\nset -eu\nif (set -o pipefail) 2>/dev/null; then set -o pipefail; fi\n")
+        };
+        let names = self.names()?;
+        for (step, name) in self.steps.iter().zip(&names) {
+            out.push('\n');
+            for line in step.comment.iter().flat_map(|c| c.lines()) {
+                out.push_str(format!("# {}", line).trim_end());
+                out.push('\n');
+            }
+            out.push_str(&format!("{}() {{\n  {}\n}}\n", name, r.render_script(&step.script)?));
+        }
+        out.push('\n');
+        for name in &names { out.push_str(name); out.push('\n'); }
+        Ok(out)
+    }
+
+    /// A `.cmd` batch file: each step is a subroutine `call`ed in turn, and
+    /// retries of a single-pipeline step are a `goto` loop with `ping` for
+    /// the (whole-second) delays. cmd.exe cannot stop a command after a
+    /// timeout, so steps with one are rejected.
+    pub fn render_batch(&self, r: &WinRenderer) -> Result<String, SyntaxError> {
+        let r = WinRenderer { batch: true, width: None, ..r.clone() };
+        let names = self.names()?;
+        let mut calls = String::new();
+        let mut subs = String::new();
+        for (step, name) in self.steps.iter().zip(&names) {
+            let mut timed = false;
+            step.script.try_map_pipes(&mut |p| {
+                timed |= p.stages.last().is_some_and(|c| c.flags.timeout_ms.is_some());
+                Ok(p.clone())
+            })?;
+            if timed {
+                return Err(SyntaxError::RenderError(format!("timeouts cannot be rendered in a batch file (step {})", name)));
+            }
+            calls.push_str(&format!("call :{} || exit /b\n", name));
+            subs.push_str(&format!("\n:{}\n", name));
+            for line in step.comment.iter().flat_map(|c| c.lines()) {
+                subs.push_str(&rem(line));
+                subs.push('\n');
+            }
+            let line = r.render_script(&step.script)?;
+            let line = if matches!(step.script, ScriptSpec::Pipe(_)) { line } else { format!("({})", line) };
+            match &step.script {
+                ScriptSpec::Pipe(p) if p.stages.last().is_some_and(|c| c.flags.retry.retries() > 0) => {
+                    subs.push_str(&retry_batch(name, &line, p)?);
+                }
+                _ => subs.push_str(&format!("{}\nexit /b\n", line)),
+            }
+        }
+        Ok(format!("@echo off\nsetlocal\n{}exit /b 0\n{}", calls, subs))
+    }
+
+    /// `step_<name>` for each step; names must be unique words.
+    fn names(&self) -> Result<Vec<String>, SyntaxError> {
+        let mut names: Vec<String> = Vec::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let name = match &step.name {
+                Some(n) if !n.is_empty() && n.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => format!("step_{}", n),
+                Some(n) => return Err(SyntaxError::RenderError(format!("step name {:?} must be letters, digits and '_'", n))),
+                None => format!("step_{}", i + 1),
+            };
+            if names.contains(&name) {
+                return Err(SyntaxError::RenderError(format!("duplicate step name {:?}", name)));
+            }
+            names.push(name);
+        }
+        Ok(names)
+    }
+}
+
+/// A batch comment. `%` is doubled (REM still expands it) and a trailing
+/// `^` escaped so it does not continue onto the next line.
+fn rem(text: &str) -> String {
+    let mut line = format!("rem {}", text.replace('%', "%%")).trim_end().to_string();
+    if line.ends_with('^') { line.push('^'); }
+    line
+}
+
+/// Retry loop for a batch subroutine:
+///
+/// ```text
+/// :step_x_retry
+/// LINE && exit /b 0
+/// set sx_s=%errorlevel%
+/// if not %sx_s%==75 exit /b %sx_s%
+/// set /a sx_i+=1
+/// if %sx_i%==1 ping -n 2 127.0.0.1 >nul & goto step_x_retry
+/// exit /b %sx_s%
+/// ```
+fn retry_batch(name: &str, line: &str, pipe: &PipelineSpec) -> Result<String, SyntaxError> {
+    let policy = &pipe.stages.last().expect("checked by caller").flags.retry;
+    if !policy.on_stderr.is_empty() {
+        return Err(SyntaxError::RenderError("stderr retry conditions cannot be rendered in a batch file".into()));
+    }
+    let mut out = format!("set sx_i=0\n:{n}_retry\n{} && exit /b 0\nset sx_s=%errorlevel%\n", line, n = name);
+    if !policy.on_status.is_empty() {
+        let tests: String = policy.on_status.iter().map(|c| format!("if not %sx_s%=={} ", c)).collect();
+        out.push_str(&format!("{}exit /b %sx_s%\n", tests));
+    }
+    out.push_str("set /a sx_i+=1\n");
+    for n in 1..=policy.retries() {
+        // `ping -n K` waits K-1 seconds
+        let wait = policy.delay(n).as_millis().div_ceil(1000) as u64;
+        let sleep = if wait > 0 { format!("ping -n {} 127.0.0.1 >nul & ", wait + 1) } else { String::new() };
+        out.push_str(&format!("if %sx_i%=={} {}goto {}_retry\n", n, sleep, name));
+    }
+    out.push_str("exit /b %sx_s%\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn sh_script_steps_and_constructs() {
+        let script = ScriptFile::new()
+            .step(Step::new(CommandSpec::new("make").cwd("/src").env("CC", "clang")).name("build").comment("Compile\nall targets"))
+            .step(CommandSpec::new("curl").args(["-fsS", "https://example.com"]).timeout(Duration::from_millis(1500)).retries(2))
+            .step(ScriptSpec::from(CommandSpec::new("diff").args(["a", "b"]).success_statuses([0, 1])).and_then(CommandSpec::new("echo").arg("same")));
+        assert_eq!(script.render_sh(&PosixRenderer::default()).unwrap(), r#"#!/bin/sh
+set -eu
+if (set -o pipefail) 2>/dev/null; then set -o pipefail; fi
+
+# Compile
+# all targets
+step_build() {
+  (cd '/src' && CC='clang' make)
+}
+
+step_2() {
+  (for sx_d in 0 0 -; do timeout 1.5 sh -c 'curl '"'"'-fsS'"'"' '"'"'https://example.com'"'"'' && exit 0; sx_s=$?; [ "$sx_d" = - ] && exit $sx_s; sleep "$sx_d"; done)
+}
+
+step_3() {
+  { diff 'a' 'b' && sx_s=0 || sx_s=$?; case $sx_s in 0|1) ;; *) (exit $((sx_s ? sx_s : 1))) ;; esac; } && echo 'same'
+}
+
+step_build
+step_2
+step_3
+"#);
+        assert!(ScriptFile::new().step(Step::new(CommandSpec::new("x")).name("a-b")).render_sh(&PosixRenderer::default()).is_err());
+        assert!(ScriptFile::new().step(CommandSpec::new("x")).step(Step::new(CommandSpec::new("y")).name("1"))
+            .render_sh(&PosixRenderer::default()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sh_script_runs_and_stops_at_failure() {
+        let dir = std::env::temp_dir().join(format!("sx-script-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log");
+        let script = ScriptFile::new()
+            .step(CommandSpec::new("echo").arg("one").stdout_append(&log))
+            .step(CommandSpec::new("sh").args(["-c", "exit 1"]).success_statuses([0, 1]))
+            .step(CommandSpec::new("sh").args(["-c", "sleep 5"]).timeout(Duration::from_millis(100)))
+            .step(CommandSpec::new("echo").arg("never").stdout_append(&log));
+        let out = std::process::Command::new("sh").args(["-c", &script.render_sh(&PosixRenderer::default()).unwrap()]).output().unwrap();
+        assert_eq!(out.status.code(), Some(124));
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "one\n");
+        // exit 0 is a failure when the accepted statuses leave it out
+        let script = ScriptFile::new()
+            .step(CommandSpec::new("true").success_statuses([1]))
+            .step(CommandSpec::new("echo").arg("never").stdout_append(&log));
+        let out = std::process::Command::new("sh").args(["-c", &script.render_sh(&PosixRenderer::default()).unwrap()]).output().unwrap();
+        assert_eq!(out.status.code(), Some(1));
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "one\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn batch_script_subroutines_and_retries() {
+        let script = ScriptFile::new()
+            .step(Step::new(CommandSpec::new("nmake").cwd("C:\\src")).name("build").comment("100% done^"))
+            .step(CommandSpec::new("curl").arg("-f").retry(RetryPolicy::attempts(3).on_status(7).backoff(crate::cmd::Backoff::Fixed { delay_ms: 1500 })))
+            .step(ScriptSpec::from(CommandSpec::new("a")).then(CommandSpec::new("b")));
+        assert_eq!(script.render_batch(&WinRenderer::default()).unwrap(), "@echo off\nsetlocal\n\
+            call :step_build || exit /b\ncall :step_2 || exit /b\ncall :step_3 || exit /b\nexit /b 0\n\
+            \n:step_build\nrem 100%% done^^\n(setlocal && cd /d C:\\src && nmake & endlocal)\nexit /b\n\
+            \n:step_2\nset sx_i=0\n:step_2_retry\ncurl -f && exit /b 0\nset sx_s=%errorlevel%\nif not %sx_s%==7 exit /b %sx_s%\nset /a sx_i+=1\n\
+            if %sx_i%==1 ping -n 3 127.0.0.1 >nul & goto step_2_retry\nif %sx_i%==2 ping -n 3 127.0.0.1 >nul & goto step_2_retry\nexit /b %sx_s%\n\
+            \n:step_3\n(a & b)\nexit /b\n");
+        let timed = ScriptFile::new().step(CommandSpec::new("x").timeout(Duration::from_secs(1)));
+        assert!(timed.render_batch(&WinRenderer::default()).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Task-file recipes: Makefiles and Justfiles.
+
+use super::{PosixRenderer, QuotePolicy, Renderer};
+use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec};
+use crate::error::SyntaxError;
+
+/// Renders Makefile recipe lines. Each line is a POSIX line (see
+/// [`PosixRenderer`], with retries and timeouts as shell constructs) with
+/// `$` written `$$`. Make runs every recipe line in its own shell, so `cd`
+/// and env never carry over from one line to the next.
+///
+/// A recipe line cannot span lines, so in-memory stdin ending in a newline
+/// (a here-doc) and values containing newlines are rejected, as are lines
+/// starting with Make's `@`, `-` or `+` prefixes.
+#[derive(Debug, Clone)]
+pub struct MakeRenderer { pub quote: QuotePolicy }
+
+impl Default for MakeRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+
+/// Renders Justfile recipe lines: POSIX lines as for [`MakeRenderer`], with
+/// `{{` written `{{{{` so just does not interpolate it. Each line runs in its
+/// own shell, as in Make.
+#[derive(Debug, Clone)]
+pub struct JustRenderer { pub quote: QuotePolicy }
+
+impl Default for JustRenderer { fn default() -> Self { Self { quote: QuotePolicy::Strict } } }
+
+impl Renderer for MakeRenderer {
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe(&cmd.clone().into()) }
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe_plan(&cmd.clone().into()) }
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> { make_line(posix(self.quote).render_pipe(pipe)?) }
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> { make_line(posix(self.quote).render_pipe_plan(pipe)?) }
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> { make_line(posix(self.quote).render_script(script)?) }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> { make_line(posix(self.quote).render_script_plan(script)?) }
+}
+
+impl Renderer for JustRenderer {
+    fn render_cmd(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe(&cmd.clone().into()) }
+    fn render_cmd_plan(&self, cmd: &CommandSpec) -> Result<String, SyntaxError> { self.render_pipe_plan(&cmd.clone().into()) }
+    fn render_pipe(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> { just_line(posix(self.quote).render_pipe(pipe)?) }
+    fn render_pipe_plan(&self, pipe: &PipelineSpec) -> Result<String, SyntaxError> { just_line(posix(self.quote).render_pipe_plan(pipe)?) }
+    fn render_script(&self, script: &ScriptSpec) -> Result<String, SyntaxError> { just_line(posix(self.quote).render_script(script)?) }
+    fn render_script_plan(&self, script: &ScriptSpec) -> Result<String, SyntaxError> { just_line(posix(self.quote).render_script_plan(script)?) }
+}
+
+impl MakeRenderer {
+    /// A rule `name:` with one tab-indented recipe line per pipeline.
+    pub fn render_recipe(&self, name: &str, pipes: &[PipelineSpec]) -> Result<String, SyntaxError> {
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || matches!(c, ':' | '=' | '#' | '$' | '%' | ';' | '\\')) {
+            return Err(SyntaxError::RenderError(format!("invalid make target {:?}", name)));
+        }
+        let mut out = format!("{}:\n", name);
+        for p in pipes { out.push_str(&format!("\t{}\n", self.render_pipe(p)?)); }
+        Ok(out)
+    }
+}
+
+impl JustRenderer {
+    /// A recipe `name:` with one indented line per pipeline.
+    pub fn render_recipe(&self, name: &str, pipes: &[PipelineSpec]) -> Result<String, SyntaxError> {
+        let ident = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+        if !ident {
+            return Err(SyntaxError::RenderError(format!("invalid just recipe name {:?}", name)));
+        }
+        let mut out = format!("{}:\n", name);
+        for p in pipes { out.push_str(&format!("    {}\n", self.render_pipe(p)?)); }
+        Ok(out)
+    }
+}
+
+/// Recipes have no executor, so retries and timeouts become shell code.
+fn posix(quote: QuotePolicy) -> PosixRenderer {
+    PosixRenderer { quote, retry_loops: true, timeouts: true, ..Default::default() }
+}
+
+fn recipe_line(line: &str, prefixes: &[char], tool: &str) -> Result<(), SyntaxError> {
+    if line.contains('\n') {
+        return Err(SyntaxError::RenderError(format!("{} recipe lines cannot contain newlines or here-docs", tool)));
+    }
+    if line.starts_with(prefixes) {
+        return Err(SyntaxError::RenderError(format!("{} would read the start of the line as a recipe prefix", tool)));
+    }
+    Ok(())
+}
+
+fn make_line(line: String) -> Result<String, SyntaxError> {
+    recipe_line(&line, &['@', '-', '+'], "make")?;
+    Ok(line.replace('$', "$$"))
+}
+
+fn just_line(line: String) -> Result<String, SyntaxError> {
+    recipe_line(&line, &['@', '-'], "just")?;
+    Ok(line.replace("{{", "{{{{"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn steps() -> Vec<PipelineSpec> {
+        vec![
+            CommandSpec::new("cargo").args(["build", "--target-dir", "$HOME/t"]).cwd("/src").env("RUSTFLAGS", "-D warnings").into(),
+            CommandSpec::new("echo").arg("{{not a var}}").pipe(CommandSpec::new("tee").arg("out.txt")),
+            CommandSpec::new("curl").arg("-f").timeout(Duration::from_secs(5)).into(),
+        ]
+    }
+
+    #[test]
+    fn make_recipe_escapes_dollars() {
+        let r = MakeRenderer { quote: QuotePolicy::Loose };
+        assert_eq!(r.render_recipe("build", &steps()).unwrap(), concat!(
+            "build:\n",
+            "\tcd /src && RUSTFLAGS='-D warnings' cargo build --target-dir '$$HOME/t'\n",
+            "\techo '{{not a var}}' | tee out.txt\n",
+            "\ttimeout 5 sh -c 'curl -f'\n",
+        ));
+        assert!(r.render_cmd(&CommandSpec::new("cat").stdin_text("x\n")).is_err());
+        assert_eq!(r.render_cmd(&CommandSpec::new("cat").stdin_text("$x")).unwrap(), "printf '%s' '$$x' | cat");
+        assert!(r.render_cmd(&CommandSpec::new("-x")).is_err());
+        assert!(r.render_recipe("a b", &[]).is_err());
+    }
+
+    #[test]
+    fn just_recipe_escapes_braces() {
+        let r = JustRenderer::default();
+        assert_eq!(r.render_recipe("build-all", &steps()).unwrap(), concat!(
+            "build-all:\n",
+            "    cd '/src' && RUSTFLAGS='-D warnings' cargo 'build' '--target-dir' '$HOME/t'\n",
+            "    echo '{{{{not a var}}' | tee 'out.txt'\n",
+            "    timeout 5 sh -c 'curl '\"'\"'-f'\"'\"''\n",
+        ));
+        assert!(r.render_recipe("1st", &[]).is_err());
+    }
+
+    /// Make hands each line, `$$` undone, to its own `sh`.
+    #[cfg(unix)]
+    #[test]
+    fn make_runs_recipe_lines() {
+        if std::process::Command::new("make").arg("--version").output().is_err() { return; }
+        let dir = std::env::temp_dir().join(format!("sx-make-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let recipe = MakeRenderer::default().render_recipe("all", &[
+            CommandSpec::new("sh").args(["-c", "printf '%s|' \"$PWD\" \"$0\" \"$V\" > out", "$x it's"]).cwd("sub").env("V", "$$ {{v}}").into(),
+            CommandSpec::new("cat").arg("sub/out").into(),
+        ]).unwrap();
+        std::fs::write(dir.join("Makefile"), recipe).unwrap();
+        let out = std::process::Command::new("make").args(["-s", "-C"]).arg(&dir).output().unwrap();
+        let want = format!("{}|$x it's|$$ {{{{v}}}}|", dir.join("sub").canonicalize().unwrap().display());
+        assert_eq!(String::from_utf8_lossy(&out.stdout), want);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! Serde support for the command and template ASTs (feature = "serde").
+//!
+//! Format version 2, as JSON (other serde formats follow the same shape):
+//! - `CommandSpec` and `CmdFlags` are objects. Empty or default fields are
+//!   omitted on output and may be left out on input.
+//! - `OsString` values (program, args, env values, cwd, file paths) are
+//!   strings, or `{"bytes": [..]}` holding raw Unix bytes when not UTF-8.
+//! - `Stdio`, `ScriptSpec`, `Segment` and `Arg` are tagged as
+//!   `{"kind": "<snake_case variant>", "value": ..}`; unit variants carry no
+//!   `value`, tuple variants use an array.
+//! - `PipelineSpec` is `{"stages": [..], "opts": {..}}`, with `opts` omitted
+//!   when default. `Template` is an array of segments.
+//!
+//! Version 1 wrote a `PipelineSpec` as a bare array of stages and a retry
+//! count as `flags.retries`; such documents still load. Wrap stored documents in [`Versioned`] so readers can reject
+//! formats they do not understand.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::cmd::{CommandSpec, PipeOpts, PipelineSpec, RetryPolicy};
+use crate::error::SyntaxError;
+
+/// Current representation version written by [`Versioned::new`].
+pub const FORMAT_VERSION: u32 = 2;
+
+/// A document tagged with its format version: `{"version": 2, "data": ..}`.
+/// Versions up to [`FORMAT_VERSION`] are accepted on input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    #[serde(deserialize_with = "check_version")]
+    pub version: u32,
+    pub data: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(data: T) -> Self { Versioned { version: FORMAT_VERSION, data } }
+    pub fn into_inner(self) -> T { self.data }
+}
+
+fn check_version<'de, D: Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+    let v = u32::deserialize(d)?;
+    if !(1..=FORMAT_VERSION).contains(&v) {
+        return Err(D::Error::custom(SyntaxError::InvalidArgument(format!("unsupported format version {} (expected at most {})", v, FORMAT_VERSION))));
+    }
+    Ok(v)
+}
+
+pub(crate) fn is_default<T: Default + PartialEq>(v: &T) -> bool { *v == T::default() }
+pub(crate) fn is_false(v: &bool) -> bool { !*v }
+
+/// A `PipelineSpec` as read: an object, or a version 1 array of stages.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PipelineRepr {
+    Stages(Vec<CommandSpec>),
+    Full {
+        #[serde(default)]
+        stages: Vec<CommandSpec>,
+        #[serde(default)]
+        opts: PipeOpts,
+    },
+}
+
+impl From<PipelineRepr> for PipelineSpec {
+    fn from(r: PipelineRepr) -> Self {
+        match r {
+            PipelineRepr::Stages(stages) => stages.into(),
+            PipelineRepr::Full { stages, opts } => PipelineSpec { stages, opts },
+        }
+    }
+}
+
+/// `CmdFlags::retry` as read: a policy, or a version 1 `retries` count.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RetryRepr {
+    Count(u32),
+    Policy(RetryPolicy),
+}
+
+pub(crate) fn retry<'de, D: Deserializer<'de>>(d: D) -> Result<RetryPolicy, D::Error> {
+    Ok(match RetryRepr::deserialize(d)? {
+        RetryRepr::Count(n) => RetryPolicy::attempts(n.saturating_add(1)),
+        RetryRepr::Policy(p) => p,
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum OsRepr {
+    Str(String),
+    Bytes { bytes: Vec<u8> },
+}
+
+fn to_repr(s: &OsStr) -> OsRepr {
+    match s.to_str() {
+        Some(t) => OsRepr::Str(t.to_string()),
+        None => OsRepr::Bytes { bytes: s.as_encoded_bytes().to_vec() },
+    }
+}
+
+fn from_repr<E: serde::de::Error>(r: OsRepr) -> Result<OsString, E> {
+    match r {
+        OsRepr::Str(s) => Ok(s.into()),
+        #[cfg(unix)]
+        OsRepr::Bytes { bytes } => Ok(std::os::unix::ffi::OsStringExt::from_vec(bytes)),
+        #[cfg(not(unix))]
+        OsRepr::Bytes { bytes } => String::from_utf8(bytes).map(Into::into).map_err(|_| E::custom("non-UTF-8 bytes are not supported on this platform")),
+    }
+}
+
+pub(crate) mod os {
+    use super::*;
+    pub fn serialize<S: Serializer>(v: &OsString, s: S) -> Result<S::Ok, S::Error> { to_repr(v).serialize(s) }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<OsString, D::Error> { from_repr(OsRepr::deserialize(d)?) }
+}
+
+pub(crate) mod os_opt {
+    use super::*;
+    pub fn serialize<S: Serializer>(v: &Option<OsString>, s: S) -> Result<S::Ok, S::Error> {
+        v.as_deref().map(to_repr).serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<OsString>, D::Error> {
+        Option::<OsRepr>::deserialize(d)?.map(from_repr).transpose()
+    }
+}
+
+pub(crate) mod os_vec {
+    use super::*;
+    pub fn serialize<S: Serializer>(v: &[OsString], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(v.iter().map(|x| to_repr(x)))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<OsString>, D::Error> {
+        Vec::<OsRepr>::deserialize(d)?.into_iter().map(from_repr).collect()
+    }
+}
+
+pub(crate) mod os_map {
+    use super::*;
+    pub fn serialize<S: Serializer>(v: &BTreeMap<String, OsString>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_map(v.iter().map(|(k, x)| (k, to_repr(x))))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeMap<String, OsString>, D::Error> {
+        BTreeMap::<String, OsRepr>::deserialize(d)?.into_iter().map(|(k, r)| Ok((k, from_repr(r)?))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{CommandSpec, PipelineSpec, ScriptSpec, Stdio};
+    use crate::tmpl::Template;
+    use std::time::Duration;
+
+    #[test]
+    fn command_representation_is_stable() {
+        let cmd = CommandSpec::new("grep").args(["-n", "todo"]).env("LC_ALL", "C").stderr(Stdio::Dup(1));
+        let json = serde_json::to_string(&Versioned::new(PipelineSpec::from(cmd))).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":2,"data":{"stages":[{"program":"grep","args":["-n","todo"],"env":{"LC_ALL":"C"},"stderr":{"kind":"dup","value":1}}]}}"#
+        );
+        let minimal: CommandSpec = serde_json::from_str(r#"{"program":"ls","stdout":{"kind":"null"}}"#).unwrap();
+        assert_eq!(minimal, CommandSpec::new("ls").stdout(Stdio::Null));
+    }
+
+    #[test]
+    fn round_trips_pipelines_scripts_and_templates() {
+        let pipe = CommandSpec::new("cat")
+            .stdin_text("cfg\n")
+            .cwd("/srv")
+            .env_clear()
+            .env_keep("PATH")
+            .env_remove("LD_PRELOAD")
+            .timeout(Duration::from_millis(250))
+            .pipe(CommandSpec::new("tee").arg("out").stdout_append("log").background())
+            .pipefail();
+        let script = ScriptSpec::from(pipe.clone()).and_then(CommandSpec::new("true")).or_else(CommandSpec::new("false").stdin(Stdio::Bytes(vec![0, 1]))).subshell();
+        let json = serde_json::to_string(&Versioned::new(script.clone())).unwrap();
+        let back: Versioned<ScriptSpec> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.into_inner(), script);
+
+        let tpl = Template::parse_jynx("%for:item(a,b)([${item}])(,) %color:red(hi ${USER})").unwrap();
+        let json = serde_json::to_string(&tpl).unwrap();
+        assert_eq!(serde_json::from_str::<Template>(&json).unwrap(), tpl);
+        assert!(json.starts_with(r#"[{"kind":"for","value":{"var":"item","#));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_values_use_bytes() {
+        use std::os::unix::ffi::OsStringExt;
+        let cmd = CommandSpec::new("cat").arg(OsString::from_vec(b"a\xff".to_vec()));
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(json, r#"{"program":"cat","args":[{"bytes":[97,255]}]}"#);
+        assert_eq!(serde_json::from_str::<CommandSpec>(&json).unwrap(), cmd);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let err = serde_json::from_str::<Versioned<PipelineSpec>>(r#"{"version":3,"data":{"stages":[]}}"#).unwrap_err();
+        assert!(err.to_string().contains("unsupported format version 3"));
+    }
+
+    #[test]
+    fn loads_version_1_pipelines() {
+        let v1 = r#"{"version":1,"data":{"kind":"and_then","value":[{"kind":"pipe","value":[{"program":"make"}]},{"kind":"pipe","value":[{"program":"ls"},{"program":"wc"}]}]}}"#;
+        let script = serde_json::from_str::<Versioned<ScriptSpec>>(v1).unwrap().into_inner();
+        assert_eq!(script, ScriptSpec::from(CommandSpec::new("make")).and_then(CommandSpec::new("ls").pipe(CommandSpec::new("wc"))));
+
+        let v1 = r#"{"version":1,"data":[{"program":"curl","flags":{"retries":2}}]}"#;
+        let pipe = serde_json::from_str::<Versioned<PipelineSpec>>(v1).unwrap().into_inner();
+        assert_eq!(pipe, PipelineSpec::from(CommandSpec::new("curl").retries(2)));
+        assert_eq!(pipe.stages[0].flags.retry.max_attempts, 3);
+        let v2 = serde_json::to_string(&Versioned::new(pipe.clone())).unwrap();
+        assert!(v2.contains(r#""retry":{"max_attempts":3"#), "{}", v2);
+        assert_eq!(serde_json::from_str::<Versioned<PipelineSpec>>(&v2).unwrap().into_inner(), pipe);
+    }
+}